@@ -1,20 +1,89 @@
+const PI: f32 = 3.14159265359;
+
+// Bits in Material.textures, in binding order
+const BASE_COLOUR_TEXTURE: u32 = 1u;
+const METALLIC_ROUGHNESS_TEXTURE: u32 = 2u;
+const NORMAL_TEXTURE: u32 = 4u;
+const OCCLUSION_TEXTURE: u32 = 8u;
+const EMISSIVE_TEXTURE: u32 = 16u;
+
+// Material.alpha_mode
+const ALPHA_OPAQUE: u32 = 0u;
+const ALPHA_MASK: u32 = 1u;
+const ALPHA_BLEND: u32 = 2u;
+
+struct Camera {
+	view_perspective: mat4x4<f32>,
+	position: vec3<f32>,
+}
+
 struct Material {
     base_colour: vec4<f32>,
+	emissive: vec3<f32>,
 	metallic: f32,
 	roughness: f32,
-	has_texture: u32,
+	normal_scale: f32,
+	occlusion_strength: f32,
+	textures: u32,
+	alpha_mode: u32,
+	alpha_cutoff: f32,
 }
 
+const POINT_LIGHT: u32 = 0u;
+const DIRECTIONAL_LIGHT: u32 = 1u;
+const SPOT_LIGHT: u32 = 2u;
+
 struct Light {
 	position: vec3<f32>,
-	colour: vec3<f32>,
+	kind: u32,
+	direction: vec3<f32>,
 	intensity: f32,
+	colour: vec3<f32>,
+	inner_cone: f32,
+	outer_cone: f32,
+	range: f32,
+}
+
+struct Lights {
+	// Already scaled by its strength
+	ambient: vec3<f32>,
+	count: u32,
+	lights: array<Light>,
+}
+
+struct Shadow {
+	view_projection: mat4x4<f32>,
+	// Index into lights of the light casting shadows
+	light: u32,
 }
 
+// World units to push the lookup along the normal, hides shadow acne
+const SHADOW_NORMAL_OFFSET: f32 = 0.01;
+
+// InstanceInput.joint_offset of entities without joint matrices
+const NO_JOINTS: u32 = 0xffffffffu;
+
 struct VertexInput {
     @location(0) vertex: vec3<f32>,
 	@location(1) normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
+	// Up to four joints moving the vertex and how much each counts
+	@location(10) joints: vec4<u32>,
+	@location(11) weights: vec4<f32>,
+}
+
+// Columns of the per instance model transform
+struct InstanceInput {
+	@location(3) model_0: vec4<f32>,
+	@location(4) model_1: vec4<f32>,
+	@location(5) model_2: vec4<f32>,
+	@location(6) model_3: vec4<f32>,
+	// Normal matrix columns, only xyz are used
+	@location(7) normal_0: vec4<f32>,
+	@location(8) normal_1: vec4<f32>,
+	@location(9) normal_2: vec4<f32>,
+	// Where the entity's joint matrices start in joints
+	@location(12) joint_offset: u32,
 }
 
 struct VertexOutput {
@@ -25,10 +94,16 @@ struct VertexOutput {
 }
 
 @group(0) @binding(0)
-var<uniform> camera: mat4x4<f32>;
+var<uniform> camera: Camera;
 
 @group(1) @binding(0)
-var<uniform> light: Light;
+var<storage, read> lights: Lights;
+@group(1) @binding(1)
+var<uniform> shadow: Shadow;
+@group(1) @binding(2)
+var shadow_map: texture_depth_2d;
+@group(1) @binding(3)
+var shadow_sampler: sampler_comparison;
 
 @group(2) @binding(0)
 var<uniform> material: Material;
@@ -36,32 +111,237 @@ var<uniform> material: Material;
 var t_diffuse: texture_2d<f32>;
 @group(2) @binding(2)
 var s_diffuse: sampler;
+@group(2) @binding(3)
+var t_metallic_roughness: texture_2d<f32>;
+@group(2) @binding(4)
+var t_normal: texture_2d<f32>;
+@group(2) @binding(5)
+var t_occlusion: texture_2d<f32>;
+@group(2) @binding(6)
+var t_emissive: texture_2d<f32>;
+@group(2) @binding(7)
+var s_metallic_roughness: sampler;
+@group(2) @binding(8)
+var s_normal: sampler;
+@group(2) @binding(9)
+var s_occlusion: sampler;
+@group(2) @binding(10)
+var s_emissive: sampler;
 
 @group(3) @binding(0)
-var<uniform> model_transform: mat4x4<f32>;
+var<storage, read> joints: array<mat4x4<f32>>;
+
+// Blend of the joint matrices moving a vertex, identity when unskinned
+fn skin(in: VertexInput, offset: u32) -> mat4x4<f32> {
+	let total = in.weights.x + in.weights.y + in.weights.z + in.weights.w;
+	if offset == NO_JOINTS || total == 0.0 {
+		return mat4x4<f32>(
+			vec4<f32>(1.0, 0.0, 0.0, 0.0),
+			vec4<f32>(0.0, 1.0, 0.0, 0.0),
+			vec4<f32>(0.0, 0.0, 1.0, 0.0),
+			vec4<f32>(0.0, 0.0, 0.0, 1.0),
+		);
+	}
+	return joints[offset + in.joints.x] * in.weights.x
+		+ joints[offset + in.joints.y] * in.weights.y
+		+ joints[offset + in.joints.z] * in.weights.z
+		+ joints[offset + in.joints.w] * in.weights.w;
+}
 
 
 @vertex
-fn vs_main(in: VertexInput) -> VertexOutput {
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
+	let model_transform = mat4x4<f32>(
+		instance.model_0,
+		instance.model_1,
+		instance.model_2,
+		instance.model_3,
+	);
+	let skin_transform = skin(in, instance.joint_offset);
     var out: VertexOutput;
-    out.world_position = model_transform * vec4<f32>(in.vertex, 1.0);
-    out.position = camera * out.world_position;
+    out.world_position = model_transform * skin_transform * vec4<f32>(in.vertex, 1.0);
+    out.position = camera.view_perspective * out.world_position;
     out.uv = in.uv;
-    out.normal = normalize((model_transform * vec4<f32>(in.normal, 0.0)).xyz);
+	let normal_matrix = mat3x3<f32>(
+		instance.normal_0.xyz,
+		instance.normal_1.xyz,
+		instance.normal_2.xyz,
+	);
+	// Joints are not expected to scale unevenly, so the skin matrix is
+	// used for normals as is
+	let skin_normal = mat3x3<f32>(
+		skin_transform[0].xyz,
+		skin_transform[1].xyz,
+		skin_transform[2].xyz,
+	);
+    out.normal = normalize(normal_matrix * skin_normal * in.normal);
     return out;
 }
 
+fn has_texture(texture: u32) -> bool {
+	return (material.textures & texture) != 0u;
+}
+
+// Trowbridge-Reitz GGX normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+	let a = roughness * roughness;
+	let a2 = a * a;
+	let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+	return a2 / (PI * d * d);
+}
+
+// Smith's method with Schlick-GGX for both the view and light directions
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+	let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+	let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+	let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+	return g_v * g_l;
+}
+
+// Direction towards the light and how much of its radiance arrives
+struct Incoming {
+	direction: vec3<f32>,
+	radiance: vec3<f32>,
+}
+
+fn incoming_light(light: Light, world_position: vec3<f32>) -> Incoming {
+	var incoming: Incoming;
+	if light.kind == DIRECTIONAL_LIGHT {
+		incoming.direction = -light.direction;
+		incoming.radiance = light.colour * light.intensity;
+		return incoming;
+	}
+
+	let to_light = light.position - world_position;
+	let distance = length(to_light);
+	incoming.direction = to_light / distance;
+
+	// Inverse square falloff, windowed to reach zero at the range
+	var attenuation = 1.0 / max(distance * distance, 0.0001);
+	if light.range > 0.0 {
+		let ratio = distance / light.range;
+		let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+		attenuation *= window * window;
+	}
+	if light.kind == SPOT_LIGHT {
+		let theta = dot(-incoming.direction, light.direction);
+		attenuation *= smoothstep(light.outer_cone, light.inner_cone, theta);
+	}
+
+	incoming.radiance = light.colour * light.intensity * attenuation;
+	return incoming;
+}
+
+// How much of the shadow casting light reaches a point, 3x3 PCF on top of
+// the comparison sampler's bilinear filtering
+fn shadow_visibility(world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+	let offset = world_position + normal * SHADOW_NORMAL_OFFSET;
+	let clip = shadow.view_projection * vec4<f32>(offset, 1.0);
+	let ndc = clip.xyz / clip.w;
+	// Outside the shadow map counts as lit
+	if any(abs(ndc.xy) > vec2<f32>(1.0)) || ndc.z > 1.0 {
+		return 1.0;
+	}
+
+	let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+	let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+	var visibility = 0.0;
+	for (var y = -1; y <= 1; y++) {
+		for (var x = -1; x <= 1; x++) {
+			let tap = uv + vec2<f32>(f32(x), f32(y)) * texel;
+			visibility += textureSampleCompareLevel(shadow_map, shadow_sampler, tap, ndc.z);
+		}
+	}
+	return visibility / 9.0;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+	return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// We have no vertex tangents so build the tangent frame from screen space
+// derivatives of the position and uv
+fn perturb_normal(normal: vec3<f32>, position: vec3<f32>, uv: vec2<f32>) -> vec3<f32> {
+	let uv_dx = dpdx(uv);
+	let uv_dy = dpdy(uv);
+	let tangent = (uv_dy.y * dpdx(position) - uv_dx.y * dpdy(position))
+		/ (uv_dx.x * uv_dy.y - uv_dy.x * uv_dx.y);
+	let t = normalize(tangent - normal * dot(normal, tangent));
+	let b = cross(normal, t);
+
+	var sampled = textureSample(t_normal, s_normal, uv).xyz * 2.0 - 1.0;
+	sampled = vec3<f32>(sampled.xy * material.normal_scale, sampled.z);
+	return normalize(mat3x3<f32>(t, b, normal) * sampled);
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     var colour = material.base_colour;
-    if material.has_texture == 1 {
+    if has_texture(BASE_COLOUR_TEXTURE) {
         colour *= textureSample(t_diffuse, s_diffuse, in.uv);
     }
+	if material.alpha_mode == ALPHA_MASK && colour.a < material.alpha_cutoff {
+		discard;
+	}
+	// Only blended materials are see through
+	if material.alpha_mode != ALPHA_BLEND {
+		colour.a = 1.0;
+	}
+
+	var metallic = material.metallic;
+	var roughness = material.roughness;
+	if has_texture(METALLIC_ROUGHNESS_TEXTURE) {
+		let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, in.uv);
+		roughness *= metallic_roughness.g;
+		metallic *= metallic_roughness.b;
+	}
+	// Perfectly smooth surfaces make the highlight vanish
+	roughness = clamp(roughness, 0.04, 1.0);
+
+	var normal = normalize(in.normal);
+	if has_texture(NORMAL_TEXTURE) {
+		normal = perturb_normal(normal, in.world_position.xyz, in.uv);
+	}
+
+	var occlusion = 1.0;
+	if has_texture(OCCLUSION_TEXTURE) {
+		let sampled = textureSample(t_occlusion, s_occlusion, in.uv).r;
+		occlusion = 1.0 + material.occlusion_strength * (sampled - 1.0);
+	}
+
+	var emissive = material.emissive;
+	if has_texture(EMISSIVE_TEXTURE) {
+		emissive *= textureSample(t_emissive, s_emissive, in.uv).rgb;
+	}
+
+	let albedo = colour.rgb;
+	let view_dir = normalize(camera.position - in.world_position.xyz);
+	let n_dot_v = max(dot(normal, view_dir), 0.0001);
+	// Dielectrics reflect about 4%, metals tint the reflection with albedo
+	let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+
+	var direct = vec3<f32>(0.0);
+	for (var i = 0u; i < lights.count; i++) {
+		var incoming = incoming_light(lights.lights[i], in.world_position.xyz);
+		if i == shadow.light {
+			incoming.radiance *= shadow_visibility(in.world_position.xyz, normal);
+		}
+		let halfway = normalize(view_dir + incoming.direction);
+
+		let n_dot_l = max(dot(normal, incoming.direction), 0.0);
+		let n_dot_h = max(dot(normal, halfway), 0.0);
+		let h_dot_v = max(dot(halfway, view_dir), 0.0);
+
+		let fresnel = fresnel_schlick(h_dot_v, f0);
+		let specular = distribution_ggx(n_dot_h, roughness)
+			* geometry_smith(n_dot_v, n_dot_l, roughness)
+			* fresnel / (4.0 * n_dot_v * max(n_dot_l, 0.0001));
+		let diffuse = (1.0 - fresnel) * (1.0 - metallic) * albedo / PI;
 
-    let light_dir = normalize(light.position - in.world_position.xyz);
-    let diffuse_strength = max(dot(in.normal, light_dir), 0.0);
-    let diffuse = light.colour * diffuse_strength * light.intensity;
-    let lit_colour = vec3<f32>(colour.rgb) * diffuse;
+		direct += (diffuse + specular) * incoming.radiance * n_dot_l;
+	}
+	// Stand in for indirect light so unlit sides are not pure black
+	let ambient = lights.ambient * albedo * occlusion;
 
-    return vec4<f32>(lit_colour, colour.a);
+    return vec4<f32>(direct + ambient + emissive, colour.a);
 }
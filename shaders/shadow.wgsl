@@ -0,0 +1,90 @@
+// Depth only pass rendering the scene from the shadow casting light. Masked
+// meshes also run fs_masked so their cut out parts cast no shadow
+
+struct Shadow {
+	view_projection: mat4x4<f32>,
+	light: u32,
+}
+
+const NO_JOINTS: u32 = 0xffffffffu;
+const BASE_COLOUR_TEXTURE: u32 = 1u;
+
+@group(0) @binding(0)
+var<uniform> shadow: Shadow;
+
+@group(1) @binding(0)
+var<storage, read> joints: array<mat4x4<f32>>;
+
+// Only the part of the main shader's Material the cutoff needs
+struct Material {
+	base_colour: vec4<f32>,
+	emissive: vec3<f32>,
+	metallic: f32,
+	roughness: f32,
+	normal_scale: f32,
+	occlusion_strength: f32,
+	textures: u32,
+	alpha_mode: u32,
+	alpha_cutoff: f32,
+}
+
+@group(2) @binding(0)
+var<uniform> material: Material;
+@group(2) @binding(1)
+var t_diffuse: texture_2d<f32>;
+@group(2) @binding(2)
+var s_diffuse: sampler;
+
+struct VertexInput {
+	@location(0) vertex: vec3<f32>,
+	@location(2) uv: vec2<f32>,
+	@location(3) model_0: vec4<f32>,
+	@location(4) model_1: vec4<f32>,
+	@location(5) model_2: vec4<f32>,
+	@location(6) model_3: vec4<f32>,
+	@location(10) joints: vec4<u32>,
+	@location(11) weights: vec4<f32>,
+	@location(12) joint_offset: u32,
+}
+
+// Same skinning as the main shader so shadows follow the pose
+fn skin(in: VertexInput) -> mat4x4<f32> {
+	let total = in.weights.x + in.weights.y + in.weights.z + in.weights.w;
+	if in.joint_offset == NO_JOINTS || total == 0.0 {
+		return mat4x4<f32>(
+			vec4<f32>(1.0, 0.0, 0.0, 0.0),
+			vec4<f32>(0.0, 1.0, 0.0, 0.0),
+			vec4<f32>(0.0, 0.0, 1.0, 0.0),
+			vec4<f32>(0.0, 0.0, 0.0, 1.0),
+		);
+	}
+	let offset = in.joint_offset;
+	return joints[offset + in.joints.x] * in.weights.x
+		+ joints[offset + in.joints.y] * in.weights.y
+		+ joints[offset + in.joints.z] * in.weights.z
+		+ joints[offset + in.joints.w] * in.weights.w;
+}
+
+struct VertexOutput {
+	@builtin(position) position: vec4<f32>,
+	@location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+	let model_transform = mat4x4<f32>(in.model_0, in.model_1, in.model_2, in.model_3);
+	let vertex = model_transform * skin(in) * vec4<f32>(in.vertex, 1.0);
+	return VertexOutput(shadow.view_projection * vertex, in.uv);
+}
+
+// Same cutoff as the main shader
+@fragment
+fn fs_masked(in: VertexOutput) {
+	var alpha = material.base_colour.a;
+	if (material.textures & BASE_COLOUR_TEXTURE) != 0u {
+		alpha *= textureSample(t_diffuse, s_diffuse, in.uv).a;
+	}
+	if alpha < material.alpha_cutoff {
+		discard;
+	}
+}
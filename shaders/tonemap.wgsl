@@ -0,0 +1,46 @@
+const ACES: u32 = 0u;
+const REINHARD: u32 = 1u;
+
+struct Tonemap {
+	exposure: f32,
+	tonemapper: u32,
+}
+
+@group(0) @binding(0)
+var frame: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> tonemap: Tonemap;
+
+// One triangle covering the screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	let corner = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+	return vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+	let a = 2.51;
+	let b = 0.03;
+	let c = 2.43;
+	let d = 0.59;
+	let e = 0.14;
+	return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard(x: vec3<f32>) -> vec3<f32> {
+	return x / (1.0 + x);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	// The frame is the same size as the target so texels line up with pixels
+	let hdr = textureLoad(frame, vec2<i32>(position.xy), 0).rgb * tonemap.exposure;
+	var colour: vec3<f32>;
+	if tonemap.tonemapper == REINHARD {
+		colour = reinhard(hdr);
+	} else {
+		colour = aces(hdr);
+	}
+	return vec4<f32>(colour, 1.0);
+}
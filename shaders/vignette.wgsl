@@ -0,0 +1,22 @@
+// How much darker the corners are than the middle
+const STRENGTH: f32 = 0.35;
+
+@group(0) @binding(0)
+var frame: texture_2d<f32>;
+
+// One triangle covering the screen, no vertex buffer needed
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+	let corner = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+	return vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+	let colour = textureLoad(frame, vec2<i32>(position.xy), 0);
+	// 0 in the middle of the frame and 1 in the corners
+	let size = vec2<f32>(textureDimensions(frame));
+	let offset = (position.xy / size - 0.5) * 2.0;
+	let falloff = dot(offset, offset) / 2.0;
+	return vec4<f32>(colour.rgb * (1.0 - STRENGTH * falloff), colour.a);
+}
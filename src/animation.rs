@@ -0,0 +1,819 @@
+//! Keyframe animation sampled on the CPU. Skeletons are posed from clips and
+//! turned into joint matrices for the skinning shader, the animated nodes of
+//! rigid models are moved the same way by an [`AnimationPlayer`]
+
+use std::{
+    ops::{Add, Mul},
+    rc::Rc,
+};
+
+use crate::{
+    graphics::AssetError,
+    maths::{Mat4, Quat, Vec3},
+};
+
+/// How values between two keyframes are found, glTF's sampler interpolation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    /// Holds each value until the next keyframe
+    Step,
+    /// Hermite spline through the values, with tangents stored alongside
+    /// each of them
+    CubicSpline,
+}
+
+/// A value keyframes can hold
+pub trait Keyframe:
+    Copy + Add<Output = Self> + Mul<f32, Output = Self>
+{
+    fn interpolate(&self, rhs: &Self, t: f32) -> Self;
+    /// Fix up a value made from a weighted sum, rotations have to stay unit
+    /// length
+    fn normalise_keyframe(self) -> Self {
+        self
+    }
+}
+
+impl Keyframe for Vec3 {
+    fn interpolate(&self, rhs: &Self, t: f32) -> Self {
+        *self * (1.0 - t) + *rhs * t
+    }
+}
+
+impl Keyframe for Quat {
+    fn interpolate(&self, rhs: &Self, t: f32) -> Self {
+        self.slerp(rhs, t)
+    }
+    fn normalise_keyframe(self) -> Self {
+        self.normalise()
+    }
+}
+
+/// Values of one property over time
+#[derive(Clone, Debug)]
+pub struct Keyframes<T> {
+    /// Seconds, increasing
+    times: Vec<f32>,
+    /// One per time, cubic splines store in-tangent, value and out-tangent
+    /// for each
+    values: Vec<T>,
+    interpolation: Interpolation,
+}
+
+impl<T: Keyframe> Keyframes<T> {
+    /// [`None`] when there are no keyframes or the values do not line up
+    /// with the times
+    pub fn new(
+        times: Vec<f32>,
+        values: Vec<T>,
+        interpolation: Interpolation,
+    ) -> Option<Self> {
+        let per_time = match interpolation {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        };
+        (!times.is_empty() && values.len() == times.len() * per_time).then_some(
+            Self {
+                times,
+                values,
+                interpolation,
+            },
+        )
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.times.last().copied().unwrap_or(0.0)
+    }
+
+    /// The value at `time`, held at the first and last keyframes outside
+    /// them
+    pub fn sample(&self, time: f32) -> T {
+        let next = self.times.partition_point(|&key| key <= time);
+        if next == 0 {
+            return self.value(0);
+        }
+        if next == self.times.len() {
+            return self.value(next - 1);
+        }
+        let previous = next - 1;
+        let span = self.times[next] - self.times[previous];
+        let t = (time - self.times[previous]) / span;
+
+        match self.interpolation {
+            Interpolation::Step => self.value(previous),
+            Interpolation::Linear => {
+                self.value(previous).interpolate(&self.value(next), t)
+            }
+            Interpolation::CubicSpline => {
+                let (t2, t3) = (t * t, t * t * t);
+                // Tangents are per second, scale them to the span
+                let out_tangent = self.values[previous * 3 + 2] * span;
+                let in_tangent = self.values[next * 3] * span;
+                (self.value(previous) * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + out_tangent * (t3 - 2.0 * t2 + t)
+                    + self.value(next) * (-2.0 * t3 + 3.0 * t2)
+                    + in_tangent * (t3 - t2))
+                    .normalise_keyframe()
+            }
+        }
+    }
+
+    fn value(&self, index: usize) -> T {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.values[index * 3 + 1],
+            _ => self.values[index],
+        }
+    }
+}
+
+/// Translation, rotation and scale relative to a parent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::zeroes(),
+            rotation: Quat::identity(),
+            scale: Vec3::xyz(1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_trs(self.translation, self.rotation, self.scale)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Property {
+    Translation(Keyframes<Vec3>),
+    Rotation(Keyframes<Quat>),
+    Scale(Keyframes<Vec3>),
+}
+
+/// Keyframes driving one property of one joint
+#[derive(Clone, Debug)]
+pub struct Channel {
+    /// Index of the joint in its [`Skeleton`]
+    pub target: usize,
+    pub property: Property,
+}
+
+impl Channel {
+    pub fn duration(&self) -> f32 {
+        match &self.property {
+            Property::Translation(keyframes) => keyframes.duration(),
+            Property::Rotation(keyframes) => keyframes.duration(),
+            Property::Scale(keyframes) => keyframes.duration(),
+        }
+    }
+
+    /// Overwrite the property this channel drives with its value at `time`
+    pub fn apply(&self, time: f32, transform: &mut Transform) {
+        match &self.property {
+            Property::Translation(keyframes) => {
+                transform.translation = keyframes.sample(time);
+            }
+            Property::Rotation(keyframes) => {
+                transform.rotation = keyframes.sample(time);
+            }
+            Property::Scale(keyframes) => {
+                transform.scale = keyframes.sample(time);
+            }
+        }
+    }
+}
+
+/// Channels played together under one name, like `walk` or `idle`
+#[derive(Clone, Debug)]
+pub struct Clip {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    /// Until the last keyframe of any channel
+    duration: f32,
+}
+
+impl Clip {
+    pub fn new(name: impl Into<String>, channels: Vec<Channel>) -> Self {
+        let duration =
+            channels.iter().map(Channel::duration).fold(0.0, f32::max);
+        Self {
+            name: name.into(),
+            channels,
+            duration,
+        }
+    }
+
+    pub const fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// Where playback is `delta_time` after `time`, wrapping back to the
+    /// start when `looping` and holding the last frame otherwise
+    fn advance(&self, time: f32, delta_time: f32, looping: bool) -> f32 {
+        let time = time + delta_time;
+        if looping && self.duration > 0.0 {
+            time % self.duration
+        } else {
+            time.min(self.duration)
+        }
+    }
+
+    /// Pose the joints the clip animates as they are at `time`, the rest are
+    /// left alone
+    pub fn sample(&self, time: f32, pose: &mut [Transform]) {
+        for channel in &self.channels {
+            if let Some(transform) = pose.get_mut(channel.target) {
+                channel.apply(time, transform);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Joint {
+    /// Index of the parent joint, [`None`] for the roots of the skeleton
+    pub parent: Option<usize>,
+    /// Where a root joint's parent node is in model space, or the nodes
+    /// between the parent joint and this one that no clip moves
+    pub parent_transform: Mat4,
+    /// Local transform when no clip animates the joint
+    pub rest: Transform,
+    /// Takes vertices from model space into the joint's space in bind pose
+    pub inverse_bind: Mat4,
+}
+
+/// Joint hierarchy of a skinned model and the clips that animate it
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    pub clips: Vec<Clip>,
+}
+
+impl Skeleton {
+    pub fn clip(&self, name: &str) -> Option<usize> {
+        self.clips.iter().position(|clip| clip.name == name)
+    }
+
+    pub fn rest_pose(&self) -> Vec<Transform> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+
+    /// Skinning matrix for each joint in `pose`, moving vertices from the
+    /// bind pose to where the joint is now
+    pub fn joint_matrices(&self, pose: &[Transform]) -> Vec<Mat4> {
+        let mut globals = vec![None; self.joints.len()];
+        (0..self.joints.len())
+            .map(|joint| {
+                // Our multiplication applies the left hand side first
+                self.joints[joint].inverse_bind
+                    * self.global(joint, pose, &mut globals)
+            })
+            .collect()
+    }
+
+    /// Model space transform of `joint`, memoised in `globals` since
+    /// joints are not ordered parents first
+    fn global(
+        &self,
+        joint: usize,
+        pose: &[Transform],
+        globals: &mut [Option<Mat4>],
+    ) -> Mat4 {
+        if let Some(global) = globals[joint] {
+            return global;
+        }
+        let parent_transform = self.joints[joint].parent_transform;
+        let parent = match self.joints[joint].parent {
+            Some(parent) => {
+                parent_transform * self.global(parent, pose, globals)
+            }
+            None => parent_transform,
+        };
+        let global = pose[joint].matrix() * parent;
+        globals[joint] = Some(global);
+        global
+    }
+}
+
+/// A clip being played by an [`Animator`]
+#[derive(Clone, Copy, Debug)]
+struct Layer {
+    clip: usize,
+    time: f32,
+    /// How much this clip counts for next to the others playing
+    weight: f32,
+    looping: bool,
+}
+
+/// Plays and blends the clips of a [`Skeleton`] and keeps the joint matrices
+/// for the current pose
+pub struct Animator {
+    skeleton: Rc<Skeleton>,
+    layers: Vec<Layer>,
+    joint_matrices: Vec<Mat4>,
+}
+
+impl Animator {
+    pub fn new(skeleton: Rc<Skeleton>) -> Self {
+        let joint_matrices = skeleton.joint_matrices(&skeleton.rest_pose());
+        Self {
+            skeleton,
+            layers: Vec::new(),
+            joint_matrices,
+        }
+    }
+
+    /// Play only `clip` from its start, anything else playing stops
+    pub fn play(
+        &mut self,
+        clip: &str,
+        looping: bool,
+    ) -> Result<(), AssetError> {
+        self.layers.clear();
+        self.blend(clip, 1.0, looping)
+    }
+
+    /// Mix `clip` in with `weight` alongside what is already playing. A
+    /// clip already playing keeps its time and takes the new weight
+    pub fn blend(
+        &mut self,
+        clip: &str,
+        weight: f32,
+        looping: bool,
+    ) -> Result<(), AssetError> {
+        let index = self
+            .skeleton
+            .clip(clip)
+            .ok_or_else(|| AssetError::UnknownClip(clip.to_owned()))?;
+        match self.layers.iter_mut().find(|layer| layer.clip == index) {
+            Some(layer) => {
+                layer.weight = weight;
+                layer.looping = looping;
+            }
+            None => self.layers.push(Layer {
+                clip: index,
+                time: 0.0,
+                weight,
+                looping,
+            }),
+        }
+        self.pose();
+        Ok(())
+    }
+
+    /// Go back to the rest pose
+    pub fn stop(&mut self) {
+        self.layers.clear();
+        self.pose();
+    }
+
+    /// Move every playing clip on by `delta_time` and pose the skeleton.
+    /// Clips that do not loop hold their last frame
+    pub fn advance(&mut self, delta_time: f32) {
+        for layer in &mut self.layers {
+            let clip = &self.skeleton.clips[layer.clip];
+            layer.time = clip.advance(layer.time, delta_time, layer.looping);
+        }
+        self.pose();
+    }
+
+    /// One matrix per joint of the skeleton, for the skinning shader
+    pub fn joint_matrices(&self) -> &[Mat4] {
+        &self.joint_matrices
+    }
+
+    /// Blend the playing clips by weight, joints they do not animate stay
+    /// at rest
+    fn pose(&mut self) {
+        let rest = self.skeleton.rest_pose();
+        let total: f32 = self.layers.iter().map(|layer| layer.weight).sum();
+        if total <= 0.0 {
+            self.joint_matrices = self.skeleton.joint_matrices(&rest);
+            return;
+        }
+
+        let mut blended = vec![
+            Transform {
+                translation: Vec3::zeroes(),
+                rotation: Quat::new(0.0, 0.0, 0.0, 0.0),
+                scale: Vec3::zeroes(),
+            };
+            rest.len()
+        ];
+        for layer in &self.layers {
+            let mut pose = rest.clone();
+            self.skeleton.clips[layer.clip].sample(layer.time, &mut pose);
+            let weight = layer.weight / total;
+            for (blended, pose) in blended.iter_mut().zip(pose) {
+                blended.translation += pose.translation * weight;
+                blended.scale += pose.scale * weight;
+                // q and -q are the same rotation but would cancel out
+                let rotation = if blended.rotation.dot(&pose.rotation) < 0.0 {
+                    pose.rotation * -weight
+                } else {
+                    pose.rotation * weight
+                };
+                blended.rotation = blended.rotation + rotation;
+            }
+        }
+        for transform in &mut blended {
+            transform.rotation = transform.rotation.normalise();
+        }
+        self.joint_matrices = self.skeleton.joint_matrices(&blended);
+    }
+}
+
+/// Clips moving the nodes of a rigid model, like a door swinging inside its
+/// frame. Each animated node is a joint whose inverse bind undoes where the
+/// node was when its meshes were loaded, the meshes under it follow it fully
+#[derive(Clone, Debug)]
+pub struct NodeAnimation {
+    pub nodes: Skeleton,
+    /// Where the nodes start in an entity's joint matrices, after the joints
+    /// of the model's skin
+    pub first_joint: usize,
+}
+
+/// Plays the clips of a [`NodeAnimation`] on an entity, moving each animated
+/// node of the model on top of the entity's own transform
+pub struct AnimationPlayer {
+    animation: Rc<NodeAnimation>,
+    /// Playing clip, [`None`] when stopped
+    clip: Option<usize>,
+    time: f32,
+    looping: bool,
+    node_matrices: Vec<Mat4>,
+}
+
+impl AnimationPlayer {
+    pub fn new(animation: Rc<NodeAnimation>) -> Self {
+        let nodes = &animation.nodes;
+        let node_matrices = nodes.joint_matrices(&nodes.rest_pose());
+        Self {
+            animation,
+            clip: None,
+            time: 0.0,
+            looping: false,
+            node_matrices,
+        }
+    }
+
+    /// Play `clip` from its start in place of whatever was playing
+    pub fn play(
+        &mut self,
+        clip: &str,
+        looping: bool,
+    ) -> Result<(), AssetError> {
+        let index = self
+            .animation
+            .nodes
+            .clip(clip)
+            .ok_or_else(|| AssetError::UnknownClip(clip.to_owned()))?;
+        self.clip = Some(index);
+        self.time = 0.0;
+        self.looping = looping;
+        self.pose();
+        Ok(())
+    }
+
+    /// Put the model back at rest
+    pub fn stop(&mut self) {
+        self.clip = None;
+        self.pose();
+    }
+
+    /// Whether a clip that does not loop has reached its end, or nothing
+    /// is playing
+    pub fn finished(&self) -> bool {
+        self.clip.is_none_or(|clip| {
+            let clip = &self.animation.nodes.clips[clip];
+            !self.looping && self.time >= clip.duration()
+        })
+    }
+
+    pub fn advance(&mut self, delta_time: f32) {
+        if let Some(clip) = self.clip {
+            let clip = &self.animation.nodes.clips[clip];
+            self.time = clip.advance(self.time, delta_time, self.looping);
+        }
+        self.pose();
+    }
+
+    /// One matrix per animated node, moving its meshes from where they were
+    /// loaded to where the clip has them
+    pub fn node_matrices(&self) -> &[Mat4] {
+        &self.node_matrices
+    }
+
+    /// Where [`Self::node_matrices`] go in the entity's joint matrices
+    pub fn first_joint(&self) -> usize {
+        self.animation.first_joint
+    }
+
+    fn pose(&mut self) {
+        let nodes = &self.animation.nodes;
+        let mut pose = nodes.rest_pose();
+        if let Some(clip) = self.clip {
+            nodes.clips[clip].sample(self.time, &mut pose);
+        }
+        self.node_matrices = nodes.joint_matrices(&pose);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).len() < 1e-4, "{a:?} != {b:?}");
+    }
+
+    fn keyframes(interpolation: Interpolation) -> Keyframes<Vec3> {
+        let values = match interpolation {
+            // Flat tangents
+            Interpolation::CubicSpline => vec![
+                Vec3::zeroes(),
+                Vec3::zeroes(),
+                Vec3::zeroes(),
+                Vec3::zeroes(),
+                Vec3::x() * 2.0,
+                Vec3::zeroes(),
+            ],
+            _ => vec![Vec3::zeroes(), Vec3::x() * 2.0],
+        };
+        Keyframes::new(vec![1.0, 3.0], values, interpolation).unwrap()
+    }
+
+    #[test]
+    fn keyframes_interpolate() {
+        let linear = keyframes(Interpolation::Linear);
+        assert_near(linear.sample(0.0), Vec3::zeroes());
+        assert_near(linear.sample(1.5), Vec3::x() * 0.5);
+        assert_near(linear.sample(5.0), Vec3::x() * 2.0);
+
+        let step = keyframes(Interpolation::Step);
+        assert_near(step.sample(2.9), Vec3::zeroes());
+        assert_near(step.sample(3.0), Vec3::x() * 2.0);
+
+        // Flat tangents ease in and out, half way is still half way
+        let cubic = keyframes(Interpolation::CubicSpline);
+        assert_near(cubic.sample(2.0), Vec3::x());
+        assert!(cubic.sample(1.5).x < linear.sample(1.5).x);
+        assert!(cubic.sample(2.5).x > linear.sample(2.5).x);
+    }
+
+    #[test]
+    fn mismatched_keyframes_are_rejected() {
+        let values = vec![Vec3::zeroes(); 2];
+        assert!(
+            Keyframes::new(vec![0.0, 1.0], values, Interpolation::CubicSpline)
+                .is_none()
+        );
+        assert!(
+            Keyframes::<Vec3>::new(vec![], vec![], Interpolation::Linear)
+                .is_none()
+        );
+    }
+
+    /// Two joints in a line along x, the child one unit from its parent.
+    /// `bend` turns the root a quarter turn about z over one second
+    fn arm() -> Skeleton {
+        let joint = |parent, x: f32| Joint {
+            parent,
+            parent_transform: Mat4::identity(),
+            rest: Transform {
+                translation: Vec3::new(x, 0.0, 0.0),
+                ..Default::default()
+            },
+            inverse_bind: Mat4::from_translation(Vec3::new(-x, 0.0, 0.0)),
+        };
+        let quarter = Quat::new(
+            0.0,
+            0.0,
+            (FRAC_PI_2 / 2.0).sin(),
+            (FRAC_PI_2 / 2.0).cos(),
+        );
+        let bend = Channel {
+            target: 0,
+            property: Property::Rotation(
+                Keyframes::new(
+                    vec![0.0, 1.0],
+                    vec![Quat::identity(), quarter],
+                    Interpolation::Linear,
+                )
+                .unwrap(),
+            ),
+        };
+        let stretch = Channel {
+            target: 1,
+            property: Property::Translation(
+                Keyframes::new(
+                    vec![0.0, 1.0],
+                    vec![Vec3::x(), Vec3::x() * 3.0],
+                    Interpolation::Linear,
+                )
+                .unwrap(),
+            ),
+        };
+        Skeleton {
+            joints: vec![joint(None, 0.0), joint(Some(0), 1.0)],
+            clips: vec![
+                Clip::new("bend", vec![bend]),
+                Clip::new("stretch", vec![stretch]),
+            ],
+        }
+    }
+
+    #[test]
+    fn rest_pose_is_the_bind_pose() {
+        let animator = Animator::new(Rc::new(arm()));
+        for matrix in animator.joint_matrices() {
+            assert_near(matrix.transform_point(Vec3::xyz(1.0)), Vec3::xyz(1.0));
+        }
+    }
+
+    #[test]
+    fn children_follow_their_parent() {
+        let mut animator = Animator::new(Rc::new(arm()));
+        animator.play("bend", false).unwrap();
+        animator.advance(2.0);
+
+        // The tip of the arm swings from x round to y
+        let tip = Vec3::new(2.0, 0.0, 0.0);
+        let child = animator.joint_matrices()[1];
+        assert_near(child.transform_point(tip), Vec3::new(0.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn looping_clips_wrap_around() {
+        let mut animator = Animator::new(Rc::new(arm()));
+        animator.play("stretch", true).unwrap();
+        animator.advance(1.5);
+
+        // Half way through the second loop the child is 2 along, 1 past rest
+        let child = animator.joint_matrices()[1];
+        assert_near(child.transform_point(Vec3::x()), Vec3::x() * 2.0);
+    }
+
+    #[test]
+    fn clips_blend_by_weight() {
+        let mut animator = Animator::new(Rc::new(arm()));
+        animator.play("stretch", false).unwrap();
+        animator.blend("bend", 1.0, false).unwrap();
+        animator.advance(1.0);
+
+        // Half stretched and half bent, the child sits 2 along at 45 degrees
+        let child = animator.joint_matrices()[1];
+        let angle = FRAC_PI_2 / 2.0;
+        assert_near(
+            child.transform_point(Vec3::x()),
+            Vec3::new(angle.cos(), angle.sin(), 0.0) * 2.0,
+        );
+
+        assert!(matches!(
+            animator.play("wave", true),
+            Err(AssetError::UnknownClip(_))
+        ));
+    }
+
+    /// Spins a quarter turn about y and rises to 1 over one second, loaded
+    /// sitting 1 along x
+    fn pickup(interpolation: Interpolation) -> NodeAnimation {
+        let quarter = Quat::new(
+            0.0,
+            (FRAC_PI_2 / 2.0).sin(),
+            0.0,
+            (FRAC_PI_2 / 2.0).cos(),
+        );
+        let rotations = match interpolation {
+            Interpolation::CubicSpline => vec![
+                Quat::new(0.0, 0.0, 0.0, 0.0),
+                Quat::identity(),
+                Quat::new(0.0, 0.0, 0.0, 0.0),
+                Quat::new(0.0, 0.0, 0.0, 0.0),
+                quarter,
+                Quat::new(0.0, 0.0, 0.0, 0.0),
+            ],
+            _ => vec![Quat::identity(), quarter],
+        };
+        let translations = match interpolation {
+            Interpolation::CubicSpline => vec![
+                Vec3::zeroes(),
+                Vec3::x(),
+                Vec3::zeroes(),
+                Vec3::zeroes(),
+                Vec3::new(1.0, 1.0, 0.0),
+                Vec3::zeroes(),
+            ],
+            _ => vec![Vec3::x(), Vec3::new(1.0, 1.0, 0.0)],
+        };
+        let channels = vec![
+            Channel {
+                target: 0,
+                property: Property::Rotation(
+                    Keyframes::new(vec![0.0, 1.0], rotations, interpolation)
+                        .unwrap(),
+                ),
+            },
+            Channel {
+                target: 0,
+                property: Property::Translation(
+                    Keyframes::new(vec![0.0, 1.0], translations, interpolation)
+                        .unwrap(),
+                ),
+            },
+        ];
+        let root = Joint {
+            parent: None,
+            parent_transform: Mat4::identity(),
+            rest: Transform {
+                translation: Vec3::x(),
+                ..Default::default()
+            },
+            inverse_bind: Mat4::from_translation(Vec3::new(-1.0, 0.0, 0.0)),
+        };
+        NodeAnimation {
+            nodes: Skeleton {
+                joints: vec![root],
+                clips: vec![Clip::new("spin", channels)],
+            },
+            first_joint: 0,
+        }
+    }
+
+    #[test]
+    fn players_start_at_rest() {
+        let mut player =
+            AnimationPlayer::new(Rc::new(pickup(Interpolation::Linear)));
+        assert_eq!(player.node_matrices()[0], Mat4::identity());
+        assert!(player.finished());
+
+        player.play("spin", false).unwrap();
+        assert!(!player.finished());
+        assert_near(
+            player.node_matrices()[0].transform_point(Vec3::x()),
+            Vec3::x(),
+        );
+
+        player.advance(1.0);
+        player.stop();
+        assert_near(
+            player.node_matrices()[0].transform_point(Vec3::x()),
+            Vec3::x(),
+        );
+        assert!(matches!(
+            player.play("open", false),
+            Err(AssetError::UnknownClip(_))
+        ));
+    }
+
+    #[test]
+    fn players_move_their_nodes() {
+        // A point loaded 1 along x from the origin, at the root
+        let point = Vec3::new(2.0, 0.0, 0.0);
+        let at = |interpolation, time| {
+            let mut player =
+                AnimationPlayer::new(Rc::new(pickup(interpolation)));
+            player.play("spin", false).unwrap();
+            player.advance(time);
+            player.node_matrices()[0].transform_point(point)
+        };
+
+        let end = Vec3::new(1.0, 1.0, -1.0);
+        assert_near(at(Interpolation::Linear, 1.0), end);
+        assert_near(at(Interpolation::Step, 1.0), end);
+        assert_near(at(Interpolation::CubicSpline, 1.0), end);
+        // Held once the clip ends
+        assert_near(at(Interpolation::Linear, 5.0), end);
+
+        let angle = FRAC_PI_2 / 2.0;
+        let half_way = Vec3::new(1.0 + angle.cos(), 0.5, -angle.sin());
+        assert_near(at(Interpolation::Linear, 0.5), half_way);
+        assert_near(at(Interpolation::Step, 0.5), point);
+        assert_near(at(Interpolation::CubicSpline, 0.5), half_way);
+    }
+
+    #[test]
+    fn looping_players_wrap_around() {
+        let mut player =
+            AnimationPlayer::new(Rc::new(pickup(Interpolation::Linear)));
+        player.play("spin", true).unwrap();
+        player.advance(1.25);
+        assert!(!player.finished());
+
+        let height = player.node_matrices()[0].transform_point(Vec3::x()).y;
+        assert!((height - 0.25).abs() < 1e-4, "{height}");
+    }
+}
@@ -1,13 +1,44 @@
-use crate::graphics::{MeshId, MeshInstance, State};
-use crate::maths::{Mat4, Vec3};
+use std::{f32::consts::FRAC_PI_2, iter, rc::Rc};
+
+use wgpu::Color;
+
+use crate::animation::{AnimationPlayer, Animator, NodeAnimation, Skeleton};
+use crate::graphics::{AssetError, Gpu, Light, LightId, Lights, MeshInstance};
+use crate::maths::{Mat4, Quat, Vec3};
 use crate::physics::GRAVITY;
 
+/// Behind everything the world does not cover, the same blue as the fill
+/// light from the sky
+const SKY: Color = Color {
+    r: 0.3,
+    g: 0.42,
+    b: 0.7,
+    a: 1.0,
+};
+/// Where the player carries the torch, above its centre
+const TORCH_OFFSET: Vec3 = Vec3::new(0.0, 0.5, 0.0);
+
+/// A light that moves with an entity
+#[derive(Clone, Copy)]
+pub struct AttachedLight {
+    pub id: LightId,
+    /// From the entity's position
+    pub offset: Vec3,
+}
+
 pub struct Entity {
     position: Vec3,
+    rotation: Quat,
     scale: Vec3,
     physics: bool,
     falling: bool,
     pub mesh: MeshInstance,
+    pub light: Option<AttachedLight>,
+    /// Moves the animated nodes of the model with clips from
+    /// [`Gpu::animation`]
+    pub player: Option<AnimationPlayer>,
+    /// Poses the skeleton of a skinned mesh, [`None`] draws the bind pose
+    animator: Option<Animator>,
 }
 
 impl Entity {
@@ -19,15 +50,116 @@ impl Entity {
     ) -> Self {
         Self {
             position,
+            rotation: Quat::identity(),
             scale,
             physics,
             mesh,
             falling: false,
+            light: None,
+            player: None,
+            animator: None,
         }
     }
     pub const fn position(&self) -> Vec3 {
         self.position
     }
+    pub const fn rotation(&self) -> Quat {
+        self.rotation
+    }
+    pub fn set_rotation(&mut self, rotation: Quat) {
+        self.rotation = rotation.normalise();
+    }
+    /// Turn by `rotation` on top of the current rotation
+    pub fn rotate(&mut self, rotation: Quat) {
+        self.rotation = (self.rotation * rotation).normalise();
+    }
+    pub const fn attach_light(&mut self, id: LightId, offset: Vec3) {
+        self.light = Some(AttachedLight { id, offset });
+    }
+
+    /// Animate the mesh with the clips of `skeleton`, see [`Gpu::skeleton`]
+    pub fn set_skeleton(&mut self, skeleton: Rc<Skeleton>) {
+        self.animator = Some(Animator::new(skeleton));
+    }
+
+    /// Play only the clip called `clip` from its start
+    pub fn play(
+        &mut self,
+        clip: &str,
+        looping: bool,
+    ) -> Result<(), AssetError> {
+        self.animator_mut(clip)?.play(clip, looping)
+    }
+
+    /// Mix the clip called `clip` in with the ones already playing
+    pub fn blend(
+        &mut self,
+        clip: &str,
+        weight: f32,
+        looping: bool,
+    ) -> Result<(), AssetError> {
+        self.animator_mut(clip)?.blend(clip, weight, looping)
+    }
+
+    /// Go back to the bind pose, with every animated node at rest
+    pub fn stop(&mut self) {
+        if let Some(animator) = &mut self.animator {
+            animator.stop();
+        }
+        if let Some(player) = &mut self.player {
+            player.stop();
+        }
+    }
+
+    /// Swap in the skeleton and clips of a model loaded again. What was
+    /// playing stops, joints and clips may not match the old ones
+    pub fn reload_animation(
+        &mut self,
+        skeleton: Option<Rc<Skeleton>>,
+        animation: Option<Rc<NodeAnimation>>,
+    ) {
+        if self.animator.is_some() {
+            self.animator = skeleton.map(Animator::new);
+        }
+        if self.player.is_some() {
+            self.player = animation.map(AnimationPlayer::new);
+        }
+    }
+
+    /// Skinning matrices for the current pose followed by those of the
+    /// animated nodes, [`None`] when not animated
+    pub fn joint_matrices(&self) -> Option<impl Iterator<Item = Mat4> + '_> {
+        if self.animator.is_none() && self.player.is_none() {
+            return None;
+        }
+        let skin = self
+            .animator
+            .as_ref()
+            .map_or(&[][..], |animator| animator.joint_matrices());
+        let (first_node, nodes) = match &self.player {
+            Some(player) => (player.first_joint(), player.node_matrices()),
+            None => (skin.len(), &[][..]),
+        };
+        // Without an animator the skin stays in bind pose
+        Some(
+            skin.iter()
+                .copied()
+                .chain(iter::repeat(Mat4::identity()))
+                .take(first_node)
+                .chain(nodes.iter().copied()),
+        )
+    }
+
+    /// An entity without a skeleton has no clips to play
+    fn animator_mut(
+        &mut self,
+        clip: &str,
+    ) -> Result<&mut Animator, AssetError> {
+        self.animator
+            .as_mut()
+            .ok_or_else(|| AssetError::UnknownClip(clip.to_owned()))
+    }
+
     pub const fn move_x(&mut self, delta_time: f32, x: f32) {
         self.position.x += x * delta_time;
     }
@@ -47,7 +179,7 @@ impl Entity {
     }
 
     pub fn transform(&self) -> Mat4 {
-        Mat4::from_translation(self.position) * Mat4::from_scaling(self.scale)
+        Mat4::from_trs(self.position, self.rotation, self.scale)
     }
 
     const fn check_collision(&mut self) {
@@ -63,34 +195,181 @@ impl Entity {
 
 pub struct Game {
     pub entities: Vec<Entity>,
+    /// Index in `entities` of the cube the keyboard moves
+    player: Option<usize>,
+    /// Index in `entities` of the skinned pillar
+    pillar: Option<usize>,
+    /// Index in `entities` of the gem on its plinth
+    pickup: Option<usize>,
+    /// Spot light following the player around, see [`Game::update_lights`]
+    searchlight: Option<LightId>,
+    /// The player's torch while it is put away
+    stowed_torch: Option<Light>,
 }
 impl Game {
     pub fn new() -> Self {
         Self {
             entities: Vec::new(),
+            player: None,
+            pillar: None,
+            pickup: None,
+            searchlight: None,
+            stowed_torch: None,
         }
     }
 
-    pub fn init(&mut self, state: &State) {
+    pub fn player_mut(&mut self) -> Option<&mut Entity> {
+        self.entities.get_mut(self.player?)
+    }
+    pub fn pillar_mut(&mut self) -> Option<&mut Entity> {
+        self.entities.get_mut(self.pillar?)
+    }
+    pub fn pickup_mut(&mut self) -> Option<&mut Entity> {
+        self.entities.get_mut(self.pickup?)
+    }
+
+    /// Add `entity` to the world, returning its index in `entities`
+    fn spawn(&mut self, entity: Entity) -> usize {
+        self.entities.push(entity);
+        self.entities.len() - 1
+    }
+
+    pub fn init(
+        &mut self,
+        gpu: &mut Gpu,
+        lights: &mut Lights,
+    ) -> Result<(), AssetError> {
         let ground = Entity::new(
             Vec3::zeroes(),
             Vec3::xyz(20.0),
-            state.gpu.get_mesh(MeshId::Ground),
+            gpu.get_mesh(gpu.model("ground")?),
             false,
         );
-        let cube1 = Entity::new(
+        let mut cube1 = Entity::new(
             Vec3::new(0.0, 0.0, 0.0),
             Vec3::xyz(0.3),
-            state.gpu.get_mesh(MeshId::Cube),
+            gpu.get_mesh(gpu.model("cube")?),
             true,
         );
+        // Bluish fill light from the sky
+        gpu.set_clear_colour(SKY);
+        lights.set_ambient(Vec3::new(0.6, 0.7, 1.0), 0.08);
+        // A torch the player carries around
+        let torch = lights.add(
+            Light::point(Vec3::zeroes(), Vec3::new(1.0, 0.6, 0.2), 0.5)
+                .with_range(2.0),
+        );
+        cube1.attach_light(torch, TORCH_OFFSET);
+
+        // A skinned pillar swaying while it bows, turned to sway across
+        // the view
+        let pillar_model = gpu.model("pillar")?;
+        let mut pillar = Entity::new(
+            Vec3::new(1.5, 0.0, -1.0),
+            Vec3::xyz(0.5),
+            gpu.get_mesh(pillar_model),
+            false,
+        );
+        pillar.set_rotation(Quat::from_euler(0.0, FRAC_PI_2, 0.0));
+        if let Some(skeleton) = gpu.skeleton(pillar_model) {
+            pillar.set_skeleton(skeleton);
+            pillar.play("sway", true)?;
+            pillar.blend("bow", 0.5, true)?;
+        }
+
+        // A gem spinning above its plinth, the clips only move the gem
+        let pickup_model = gpu.model("pickup")?;
+        let mut pickup = Entity::new(
+            Vec3::new(-1.5, 0.0, -1.0),
+            Vec3::xyz(0.5),
+            gpu.get_mesh(pickup_model),
+            false,
+        );
+        pickup.player = gpu.animation(pickup_model).map(AnimationPlayer::new);
+        // Lights the pickup until the first update turns it on the player
+        self.searchlight = Some(
+            lights.add(
+                Light::spot(
+                    Vec3::new(-1.5, 3.0, -1.0),
+                    -Vec3::y(),
+                    Vec3::new(0.9, 0.95, 1.0),
+                    2.0,
+                    0.2,
+                    0.35,
+                )
+                .with_range(8.0),
+            ),
+        );
+
+        self.spawn(ground);
+        self.player = Some(self.spawn(cube1));
+        self.pillar = Some(self.spawn(pillar));
+        self.pickup = Some(self.spawn(pickup));
+        Ok(())
+    }
+
+    /// Turn the searchlight to shine on the player
+    pub fn update_lights(&self, lights: &mut Lights) {
+        let player = self.player.and_then(|index| self.entities.get(index));
+        let (Some(id), Some(player)) = (self.searchlight, player) else {
+            return;
+        };
+        if let Some(light) = lights.get_mut(id) {
+            light.set_direction(player.position() - light.position());
+        }
+    }
+
+    /// Put the player's torch away, or take it back out when it is away
+    pub fn toggle_torch(&mut self, lights: &mut Lights) {
+        let Some(player) =
+            self.player.and_then(|index| self.entities.get_mut(index))
+        else {
+            return;
+        };
+        if let Some(torch) = self.stowed_torch.take() {
+            player.attach_light(lights.add(torch), TORCH_OFFSET);
+        } else if let Some(attached) = player.light.take() {
+            self.stowed_torch = lights.remove(attached.id);
+        }
+    }
+
+    /// A ground and a square grid of `cubes` identical cubes, for measuring
+    /// how rendering scales with entity count
+    pub fn init_benchmark(
+        &mut self,
+        gpu: &Gpu,
+        cubes: usize,
+    ) -> Result<(), AssetError> {
+        self.entities.push(Entity::new(
+            Vec3::zeroes(),
+            Vec3::xyz(20.0),
+            gpu.get_mesh(gpu.model("ground")?),
+            false,
+        ));
 
-        self.entities.push(ground);
-        self.entities.push(cube1);
+        let cube = gpu.model("cube")?;
+        let side = (cubes as f32).sqrt().ceil() as usize;
+        for i in 0..cubes {
+            let (row, column) = (i / side, i % side);
+            let offset = (side as f32 - 1.0) / 2.0;
+            self.entities.push(Entity::new(
+                Vec3::new(row as f32 - offset, 0.0, column as f32 - offset),
+                Vec3::xyz(0.3),
+                gpu.get_mesh(cube),
+                false,
+            ));
+        }
+        Ok(())
     }
 
     pub fn update(&mut self, delta_time: f32) {
         for entity in self.entities.iter_mut() {
+            if let Some(player) = &mut entity.player {
+                player.advance(delta_time);
+            }
+            if let Some(animator) = &mut entity.animator {
+                animator.advance(delta_time);
+            }
             if entity.physics {
                 entity.apply_gravity(delta_time);
                 entity.check_collision();
@@ -1,125 +1,1564 @@
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::Path,
+    rc::{Rc, Weak},
+};
 
-use gltf::{Document, Node, buffer::Data, image::Source, texture::Info};
-use image::{DynamicImage, ImageFormat};
+use gltf::{
+    Document, Gltf, Node,
+    animation::{self, Interpolation as GltfInterpolation, util::ReadOutputs},
+    buffer::Data,
+    image::{Data as ImageData, Format},
+    material::AlphaMode as GltfAlphaMode,
+    texture::{self, MagFilter, MinFilter, Texture, WrappingMode},
+};
+use image::{DynamicImage, ImageBuffer};
 
-use super::{Material, Mesh};
-use crate::{graphics::Vertex, maths::Vec3};
+use super::{AlphaMode, AssetError, Filter, Material, Mesh, Sampler, Wrap};
+use crate::{
+    animation::{
+        Channel, Clip, Interpolation, Joint, Keyframes, NodeAnimation,
+        Property, Skeleton, Transform,
+    },
+    graphics::Vertex,
+    maths::{Mat4, Quat, Vec2, Vec3},
+};
 
+/// Everything loaded from one glTF file
+pub struct Scene {
+    pub meshes: Vec<Mesh>,
+    /// The first skin in the file and the clips animating it
+    pub skeleton: Option<Skeleton>,
+    /// Clips moving nodes that are not joints, for rigid models
+    pub animation: Option<NodeAnimation>,
+}
+
+/// Images and materials already loaded by their content, so meshes that
+/// share them share one copy even when they come from different files. They
+/// are held weakly, dropping the last model using one frees it
+#[derive(Default)]
+pub struct AssetCache {
+    /// By a hash of the pixels, checked against the image on a hit
+    images: HashMap<u64, Weak<DynamicImage>>,
+    materials: HashMap<MaterialKey, Weak<Material>>,
+}
+
+/// Everything that tells materials apart, textures by their shared copy
+#[derive(PartialEq, Eq, Hash)]
+struct MaterialKey {
+    /// Bits of every factor
+    factors: [u32; 12],
+    textures: [Option<*const DynamicImage>; 5],
+    samplers: [Sampler; 5],
+    alpha_mode: AlphaMode,
+}
+
+impl From<&Material> for MaterialKey {
+    fn from(material: &Material) -> Self {
+        let [r, g, b, a] = material.base_colour;
+        let [emissive_r, emissive_g, emissive_b] = material.emissive;
+        Self {
+            factors: [
+                r,
+                g,
+                b,
+                a,
+                material.metallic,
+                material.roughness,
+                emissive_r,
+                emissive_g,
+                emissive_b,
+                material.normal_scale,
+                material.occlusion_strength,
+                material.alpha_cutoff,
+            ]
+            .map(f32::to_bits),
+            textures: material
+                .textures()
+                .map(|texture| texture.map(Rc::as_ptr)),
+            samplers: material.samplers,
+            alpha_mode: material.alpha_mode,
+        }
+    }
+}
+
+impl AssetCache {
+    /// `image`, or the copy already loaded when it has the same pixels
+    fn image(&mut self, image: DynamicImage) -> Rc<DynamicImage> {
+        let mut hasher = DefaultHasher::new();
+        (image.width(), image.height(), image.as_bytes()).hash(&mut hasher);
+        let key = hasher.finish();
+        if let Some(existing) = self.images.get(&key).and_then(Weak::upgrade)
+            && *existing == image
+        {
+            return existing;
+        }
+        let image = Rc::new(image);
+        self.images.insert(key, Rc::downgrade(&image));
+        image
+    }
+
+    /// Forget images and materials nothing uses any more
+    fn forget_dropped(&mut self) {
+        self.images.retain(|_, image| image.strong_count() > 0);
+        self.materials
+            .retain(|_, material| material.strong_count() > 0);
+    }
+
+    /// `material`, or the copy already loaded when it is the same
+    fn material(&mut self, material: Material) -> Rc<Material> {
+        let key = MaterialKey::from(&material);
+        if let Some(existing) = self.materials.get(&key).and_then(Weak::upgrade)
+        {
+            return existing;
+        }
+        let material = Rc::new(material);
+        self.materials.insert(key, Rc::downgrade(&material));
+        material
+    }
+}
+
+/// Images and materials already loaded from one file by their glTF index,
+/// so they are only looked up in the [`AssetCache`] once
+struct Cache<'a> {
+    shared: &'a mut AssetCache,
+    /// [`None`] could not be decoded
+    images: HashMap<usize, Option<Rc<DynamicImage>>>,
+    /// [`None`] is the default material
+    materials: HashMap<Option<usize>, Rc<Material>>,
+}
+
+/// Images that could not be read or decoded are logged and left out, so the
+/// material falls back to its placeholder texture
 fn load_texture(
-    info: Option<Info>,
-    buffer: &Vec<Data>,
-) -> Option<DynamicImage> {
-    if let Some(info) = info {
-        let image = info.texture().source().source();
-        match image {
-            Source::View { view, mime_type } => {
-                let parent_buffer_data = &buffer[view.buffer().index()].0;
-                let data = &parent_buffer_data
-                    [view.offset()..view.offset() + view.length()];
-                let mime_type = mime_type.replace('/', ".");
-
-                image::load_from_memory_with_format(
-                    data,
-                    ImageFormat::from_path(mime_type).unwrap(),
-                )
-                .ok()
-            }
-            Source::Uri { .. } => unimplemented!(),
+    texture: Option<Texture>,
+    images: &[Option<ImageData>],
+    cache: &mut Cache,
+) -> Option<Rc<DynamicImage>> {
+    let index = texture?.source().index();
+    if let Some(image) = cache.images.get(&index) {
+        return image.clone();
+    }
+    let image =
+        images[index]
+            .as_ref()
+            .and_then(|data| match decode_image(data) {
+                Ok(image) => Some(cache.shared.image(image)),
+                Err(error) => {
+                    log::warn!("skipping image {index}: {error}");
+                    None
+                }
+            });
+    cache.images.insert(index, image.clone());
+    image
+}
+
+/// The image has already been resolved, whether it is embedded in a buffer
+/// view, a relative path next to the glTF file or a base64 data URI
+fn decode_image(data: &ImageData) -> Result<DynamicImage, AssetError> {
+    let (width, height) = (data.width, data.height);
+    let pixels = data.pixels.clone();
+
+    let image = match data.format {
+        Format::R8 => ImageBuffer::from_raw(width, height, pixels)
+            .map(DynamicImage::ImageLuma8),
+        Format::R8G8 => ImageBuffer::from_raw(width, height, pixels)
+            .map(DynamicImage::ImageLumaA8),
+        Format::R8G8B8 => ImageBuffer::from_raw(width, height, pixels)
+            .map(DynamicImage::ImageRgb8),
+        Format::R8G8B8A8 => ImageBuffer::from_raw(width, height, pixels)
+            .map(DynamicImage::ImageRgba8),
+        Format::R16 => ImageBuffer::from_raw(width, height, u16s(&pixels))
+            .map(DynamicImage::ImageLuma16),
+        Format::R16G16 => ImageBuffer::from_raw(width, height, u16s(&pixels))
+            .map(DynamicImage::ImageLumaA16),
+        Format::R16G16B16 => {
+            ImageBuffer::from_raw(width, height, u16s(&pixels))
+                .map(DynamicImage::ImageRgb16)
         }
-    } else {
-        None
+        Format::R16G16B16A16 => {
+            ImageBuffer::from_raw(width, height, u16s(&pixels))
+                .map(DynamicImage::ImageRgba16)
+        }
+        Format::R32G32B32FLOAT => {
+            ImageBuffer::from_raw(width, height, f32s(&pixels))
+                .map(DynamicImage::ImageRgb32F)
+        }
+        Format::R32G32B32A32FLOAT => {
+            ImageBuffer::from_raw(width, height, f32s(&pixels))
+                .map(DynamicImage::ImageRgba32F)
+        }
+    };
+    image.ok_or_else(|| {
+        AssetError::UnsupportedImage(format!("{:?}", data.format))
+    })
+}
+
+fn u16s(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+        .collect()
+}
+
+fn f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|bytes| {
+            f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        })
+        .collect()
+}
+
+/// Smooth normals for primitives exported without them, each vertex gets the
+/// average of the faces it is part of
+fn generate_normals(positions: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::zeroes(); positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+        let face =
+            (positions[b] - positions[a]).cross(&(positions[c] - positions[a]));
+        normals[a] += face;
+        normals[b] += face;
+        normals[c] += face;
+    }
+    normals
+        .iter()
+        .map(|normal| match normal.normalise() {
+            normal if normal.len() == 0.0 => Vec3::y(),
+            normal => normal,
+        })
+        .collect()
+}
+
+/// Bake a node's world transform into its vertices so every primitive of the
+/// model is placed relative to the model's origin
+fn apply_transform(
+    transform: &Mat4,
+    positions: &mut [Vec3],
+    normals: &mut [Vec3],
+    indices: &mut [u32],
+) {
+    for position in positions {
+        *position = transform.transform_point(*position);
+    }
+
+    // Columns of the inverse transpose, scaled by the determinant, which we
+    // lose when normalising anyway
+    let [x, y, z] = [transform.x, transform.y, transform.z]
+        .map(|column| Vec3::new(column.x, column.y, column.z));
+    let (yz, zx, xy) = (y.cross(&z), z.cross(&x), x.cross(&y));
+    let determinant = x.dot(&yz);
+    for normal in normals {
+        let cofactor = yz * normal.x + zx * normal.y + xy * normal.z;
+        *normal = (cofactor * determinant.signum()).normalise();
+    }
+
+    // Mirroring turns triangles inside out, flip them so culling still works
+    if determinant < 0.0 {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+}
+
+fn load_sampler(sampler: texture::Sampler) -> Sampler {
+    let wrap = |mode| match mode {
+        WrappingMode::Repeat => Wrap::Repeat,
+        WrappingMode::MirroredRepeat => Wrap::MirroredRepeat,
+        WrappingMode::ClampToEdge => Wrap::ClampToEdge,
+    };
+    let default = Sampler::default();
+    let (min_filter, mipmap_filter) = match sampler.min_filter() {
+        None => (default.min_filter, default.mipmap_filter),
+        Some(MinFilter::Nearest) => (Filter::Nearest, None),
+        Some(MinFilter::Linear) => (Filter::Linear, None),
+        Some(MinFilter::NearestMipmapNearest) => {
+            (Filter::Nearest, Some(Filter::Nearest))
+        }
+        Some(MinFilter::LinearMipmapNearest) => {
+            (Filter::Linear, Some(Filter::Nearest))
+        }
+        Some(MinFilter::NearestMipmapLinear) => {
+            (Filter::Nearest, Some(Filter::Linear))
+        }
+        Some(MinFilter::LinearMipmapLinear) => {
+            (Filter::Linear, Some(Filter::Linear))
+        }
+    };
+    Sampler {
+        wrap_u: wrap(sampler.wrap_s()),
+        wrap_v: wrap(sampler.wrap_t()),
+        mag_filter: match sampler.mag_filter() {
+            None => default.mag_filter,
+            Some(MagFilter::Nearest) => Filter::Nearest,
+            Some(MagFilter::Linear) => Filter::Linear,
+        },
+        min_filter,
+        mipmap_filter,
+    }
+}
+
+fn load_material(
+    material: &gltf::Material,
+    images: &[Option<ImageData>],
+    cache: &mut Cache,
+) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let normal = material.normal_texture();
+    let occlusion = material.occlusion_texture();
+
+    let samplers = [
+        pbr.base_color_texture().map(|info| info.texture()),
+        pbr.metallic_roughness_texture().map(|info| info.texture()),
+        normal.as_ref().map(|normal| normal.texture()),
+        occlusion.as_ref().map(|occlusion| occlusion.texture()),
+        material.emissive_texture().map(|info| info.texture()),
+    ]
+    .map(|texture| {
+        texture.map_or_else(Sampler::default, |texture| {
+            load_sampler(texture.sampler())
+        })
+    });
+
+    Material {
+        base_colour: pbr.base_color_factor(),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        emissive: material.emissive_factor(),
+        normal_scale: normal.as_ref().map_or(1.0, |normal| normal.scale()),
+        occlusion_strength: occlusion
+            .as_ref()
+            .map_or(1.0, |occlusion| occlusion.strength()),
+        base_colour_texture: load_texture(
+            pbr.base_color_texture().map(|info| info.texture()),
+            images,
+            cache,
+        ),
+        metallic_roughness_texture: load_texture(
+            pbr.metallic_roughness_texture().map(|info| info.texture()),
+            images,
+            cache,
+        ),
+        normal_texture: load_texture(
+            normal.map(|normal| normal.texture()),
+            images,
+            cache,
+        ),
+        occlusion_texture: load_texture(
+            occlusion.map(|occlusion| occlusion.texture()),
+            images,
+            cache,
+        ),
+        emissive_texture: load_texture(
+            material.emissive_texture().map(|info| info.texture()),
+            images,
+            cache,
+        ),
+        samplers,
+        alpha_mode: match material.alpha_mode() {
+            GltfAlphaMode::Opaque => AlphaMode::Opaque,
+            GltfAlphaMode::Mask => AlphaMode::Mask,
+            GltfAlphaMode::Blend => AlphaMode::Blend,
+        },
+        // glTF's default when the file leaves it out
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
     }
 }
 
 fn process_node(
     node: Node,
+    parent_transform: Mat4,
     document: &Document,
-    buffer: &Vec<Data>,
-    models: &mut Vec<Mesh>,
-) {
+    buffer: &[Data],
+    images: &[Option<ImageData>],
+    cache: &mut Cache,
+    models: &mut Vec<(usize, Mesh)>,
+) -> Result<(), AssetError> {
+    // Our Mat4 multiplication applies the left hand side first
+    let transform = Mat4::from(node.transform().matrix()) * parent_transform;
+
     for child in node.children() {
-        process_node(child, document, buffer, models);
+        process_node(
+            child, transform, document, buffer, images, cache, models,
+        )?;
     }
 
+    // Joint matrices place skinned vertices, glTF ignores the node's own
+    let transform = match node.skin() {
+        Some(_) => Mat4::identity(),
+        None => transform,
+    };
+
     if let Some(mesh) = node.mesh() {
         for primitive in mesh.primitives() {
-            let mut vertex_buffer = Vec::with_capacity(1000);
-            let mut index_buffer = Vec::with_capacity(1000);
-
             let reader = primitive.reader(|p| Some(&buffer[p.index()]));
 
-            let vertices = reader.read_positions().unwrap();
-            let indices = reader.read_indices().unwrap().into_u32();
-            let uvs = reader.read_tex_coords(0).unwrap().into_f32();
-            if let Some(normals) = reader.read_normals() {
-                for ((vertex, uv), normal) in vertices.zip(uvs).zip(normals) {
-                    vertex_buffer.push(Vertex::new(
-                        vertex.into(),
-                        normal.into(),
-                        uv,
-                    ));
+            let mut positions: Vec<Vec3> = reader
+                .read_positions()
+                .ok_or(AssetError::MissingAttribute("POSITION"))?
+                .map(Vec3::from)
+                .collect();
+            let mut indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let count = positions.len();
+            let mut normals: Vec<Vec3> = match reader.read_normals() {
+                Some(normals) => {
+                    matching("NORMAL", count, normals.map(Vec3::from))?
                 }
-            } else {
-                for (vertex, uv) in vertices.zip(uvs) {
-                    vertex_buffer.push(Vertex::new(
-                        vertex.into(),
-                        Vec3::y(),
-                        uv,
-                    ))
+                None => generate_normals(&positions, &indices),
+            };
+            apply_transform(
+                &transform,
+                &mut positions,
+                &mut normals,
+                &mut indices,
+            );
+            let uvs: Vec<Vec2> = match reader.read_tex_coords(0) {
+                Some(uvs) => matching(
+                    "TEXCOORD_0",
+                    count,
+                    uvs.into_f32().map(Vec2::from),
+                )?,
+                None => vec![Vec2::zeroes(); count],
+            };
+            // Unskinned vertices have no weights and are left where they are
+            let joints: Vec<[u32; 4]> = match reader.read_joints(0) {
+                Some(joints) => matching(
+                    "JOINTS_0",
+                    count,
+                    joints.into_u16().map(|joints| joints.map(u32::from)),
+                )?,
+                None => vec![[0; 4]; count],
+            };
+            let weights: Vec<[f32; 4]> = match reader.read_weights(0) {
+                Some(weights) => {
+                    matching("WEIGHTS_0", count, weights.into_f32())?
                 }
-            }
+                None => vec![[0.0; 4]; count],
+            };
 
-            for index in indices {
-                index_buffer.push(index);
-            }
+            let vertex_buffer = positions
+                .into_iter()
+                .zip(normals)
+                .zip(uvs)
+                .zip(joints.into_iter().zip(weights))
+                .map(|(((vertex, normal), uv), (joints, weights))| {
+                    Vertex::new(vertex, normal, uv).skinned(joints, weights)
+                })
+                .collect();
 
-            let material = match primitive.material().index() {
-                Some(index) => {
-                    let material = document.materials().nth(index).unwrap();
-
-                    let pbr = material.pbr_metallic_roughness();
-                    let base_colour = pbr.base_color_factor();
-                    let metallic = pbr.metallic_factor();
-                    let roughness = pbr.roughness_factor();
-                    let image = load_texture(pbr.base_color_texture(), buffer);
-
-                    Material {
-                        base_colour,
-                        metallic,
-                        roughness,
-                        image,
-                    }
+            let index = primitive.material().index();
+            let material = match cache.materials.get(&index) {
+                Some(material) => material.clone(),
+                None => {
+                    let material = match index {
+                        Some(index) => load_material(
+                            &document.materials().nth(index).unwrap(),
+                            images,
+                            cache,
+                        ),
+                        None => Material::default(),
+                    };
+                    let material = cache.shared.material(material);
+                    cache.materials.insert(index, material.clone());
+                    material
                 }
-                None => Material::default(),
             };
-            models.push(Mesh::new(vertex_buffer, index_buffer, material));
+            models.push((
+                node.index(),
+                Mesh::new(vertex_buffer, indices, material),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Collect a per vertex attribute, which needs one value per position
+fn matching<T>(
+    attribute: &'static str,
+    count: usize,
+    values: impl Iterator<Item = T>,
+) -> Result<Vec<T>, AssetError> {
+    let values: Vec<T> = values.collect();
+    if values.len() != count {
+        return Err(AssetError::MismatchedAttribute(attribute));
+    }
+    Ok(values)
+}
+
+/// Parent of every node and where it sits in model space, by node index
+fn hierarchy(document: &Document) -> Vec<(Option<usize>, Mat4)> {
+    fn visit(
+        node: Node,
+        parent: Option<usize>,
+        parent_transform: Mat4,
+        nodes: &mut [(Option<usize>, Mat4)],
+    ) {
+        let transform =
+            Mat4::from(node.transform().matrix()) * parent_transform;
+        nodes[node.index()] = (parent, transform);
+        for child in node.children() {
+            visit(child, Some(node.index()), transform, nodes);
+        }
+    }
+
+    let mut nodes = vec![(None, Mat4::identity()); document.nodes().len()];
+    for scene in document.scenes() {
+        for node in scene.nodes() {
+            visit(node, None, Mat4::identity(), &mut nodes);
         }
     }
+    nodes
 }
 
-pub fn load_glb(path: impl AsRef<Path>) -> Vec<Mesh> {
-    let (document, buffer, _image) = gltf::import(&path).unwrap();
+/// The first skin's joints and every animation channel that moves them.
+/// Channels on other nodes or morph targets are skipped
+fn load_skeleton(document: &Document, buffers: &[Data]) -> Option<Skeleton> {
+    let skin = document.skins().next()?;
+    let nodes = hierarchy(document);
+    let joint_of: HashMap<usize, usize> = skin
+        .joints()
+        .enumerate()
+        .map(|(joint, node)| (node.index(), joint))
+        .collect();
+
+    let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+    let mut inverse_binds = reader
+        .read_inverse_bind_matrices()
+        .into_iter()
+        .flatten()
+        .map(Mat4::from);
+    let joints = skin
+        .joints()
+        .map(|node| {
+            let parent_node = nodes[node.index()].0;
+            let parent =
+                parent_node.and_then(|parent| joint_of.get(&parent).copied());
+            Joint {
+                parent,
+                // A joint under a joint is its direct child
+                parent_transform: match (parent, parent_node) {
+                    (None, Some(parent_node)) => nodes[parent_node].1,
+                    _ => Mat4::identity(),
+                },
+                rest: local_transform(&node),
+                // Missing inverse bind matrices are identity
+                inverse_bind: inverse_binds.next().unwrap_or(Mat4::identity()),
+            }
+        })
+        .collect();
+
+    let clips = document
+        .animations()
+        .map(|animation| {
+            let channels = animation
+                .channels()
+                .filter_map(|channel| {
+                    let target = joint_of.get(&channel.target().node().index());
+                    load_channel(&channel, buffers, *target?)
+                })
+                .collect();
+            Clip::new(clip_name(&animation), channels)
+        })
+        .collect();
+
+    Some(Skeleton { joints, clips })
+}
+
+/// Clips moving nodes that are not joints of a skin, like a door in its
+/// frame or a spinning pickup. Each animated node becomes a joint numbered
+/// from `first_joint`, returned with the joint moving each node's meshes by
+/// node index: the node's own or that of the nearest animated node above
+/// it. Meshes of skinned nodes are left to their skin
+fn load_node_animation(
+    document: &Document,
+    buffers: &[Data],
+    first_joint: usize,
+) -> Option<(NodeAnimation, Vec<Option<u32>>)> {
+    let skin_joints: Vec<usize> = document
+        .skins()
+        .flat_map(|skin| skin.joints())
+        .map(|node| node.index())
+        .collect();
+    let mut animated: Vec<usize> = document
+        .animations()
+        .flat_map(|animation| animation.channels())
+        .map(|channel| channel.target().node().index())
+        .filter(|node| !skin_joints.contains(node))
+        .collect();
+    if animated.is_empty() {
+        return None;
+    }
+    animated.sort_unstable();
+    animated.dedup();
+    let joint_of: HashMap<usize, usize> = animated
+        .iter()
+        .enumerate()
+        .map(|(joint, &node)| (node, joint))
+        .collect();
+
+    let nodes = hierarchy(document);
+    let document_nodes: Vec<Node> = document.nodes().collect();
+    // The nearest animated node above `node` and the still ones between it
+    // and the node's parent, everything above the node when none is
+    let animated_parent = |node: usize| {
+        let mut between = Mat4::identity();
+        let mut parent = nodes[node].0;
+        while let Some(node) = parent {
+            if let Some(&joint) = joint_of.get(&node) {
+                return (Some(joint), between);
+            }
+            // Our Mat4 multiplication applies the left hand side first
+            between =
+                between * Mat4::from(document_nodes[node].transform().matrix());
+            parent = nodes[node].0;
+        }
+        (None, between)
+    };
+
+    let joints = animated
+        .iter()
+        .map(|&node| {
+            let (parent, parent_transform) = animated_parent(node);
+            Joint {
+                parent,
+                parent_transform,
+                rest: local_transform(&document_nodes[node]),
+                // A node scaled to nothing was loaded flat, there is nothing
+                // to undo
+                inverse_bind: nodes[node]
+                    .1
+                    .inverse()
+                    .unwrap_or(Mat4::identity()),
+            }
+        })
+        .collect();
+    let clips = document
+        .animations()
+        .map(|animation| {
+            let channels = animation
+                .channels()
+                .filter_map(|channel| {
+                    let target = joint_of.get(&channel.target().node().index());
+                    load_channel(&channel, buffers, *target?)
+                })
+                .collect();
+            Clip::new(clip_name(&animation), channels)
+        })
+        .collect();
+    let moved_by = document_nodes
+        .iter()
+        .map(|node| {
+            if node.skin().is_some() {
+                return None;
+            }
+            let joint = joint_of
+                .get(&node.index())
+                .copied()
+                .or_else(|| animated_parent(node.index()).0)?;
+            Some((first_joint + joint) as u32)
+        })
+        .collect();
+
+    let animation = NodeAnimation {
+        nodes: Skeleton { joints, clips },
+        first_joint,
+    };
+    Some((animation, moved_by))
+}
+
+/// The node's transform relative to its parent, split into parts clips can
+/// animate
+fn local_transform(node: &Node) -> Transform {
+    let (translation, rotation, scale) = node.transform().decomposed();
+    Transform {
+        translation: translation.into(),
+        rotation: rotation.into(),
+        scale: scale.into(),
+    }
+}
+
+/// The animation's name, or its index for unnamed ones
+fn clip_name(animation: &gltf::Animation) -> String {
+    animation.name().map_or_else(
+        || format!("animation {}", animation.index()),
+        str::to_owned,
+    )
+}
+
+/// [`None`] for morph target weights and keyframes that do not line up,
+/// which are logged
+fn load_channel(
+    channel: &animation::Channel,
+    buffers: &[Data],
+    target: usize,
+) -> Option<Channel> {
+    let loaded = read_channel(channel, buffers, target);
+    if loaded.is_none() {
+        log::warn!(
+            "skipping channel {} of {}",
+            channel.index(),
+            clip_name(&channel.animation())
+        );
+    }
+    loaded
+}
+
+fn read_channel(
+    channel: &animation::Channel,
+    buffers: &[Data],
+    target: usize,
+) -> Option<Channel> {
+    let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+    let times: Vec<f32> = reader.read_inputs()?.collect();
+    let interpolation = match channel.sampler().interpolation() {
+        GltfInterpolation::Linear => Interpolation::Linear,
+        GltfInterpolation::Step => Interpolation::Step,
+        GltfInterpolation::CubicSpline => Interpolation::CubicSpline,
+    };
+    let property = match reader.read_outputs()? {
+        ReadOutputs::Translations(values) => {
+            Property::Translation(Keyframes::new(
+                times,
+                values.map(Vec3::from).collect(),
+                interpolation,
+            )?)
+        }
+        ReadOutputs::Rotations(values) => Property::Rotation(Keyframes::new(
+            times,
+            values.into_f32().map(Quat::from).collect(),
+            interpolation,
+        )?),
+        ReadOutputs::Scales(values) => Property::Scale(Keyframes::new(
+            times,
+            values.map(Vec3::from).collect(),
+            interpolation,
+        )?),
+        ReadOutputs::MorphTargetWeights(_) => return None,
+    };
+    Some(Channel { target, property })
+}
+
+pub fn load_glb(
+    path: impl AsRef<Path>,
+    cache: &mut AssetCache,
+) -> Result<Scene, AssetError> {
+    let path = path.as_ref();
+    let base = path.parent();
+    let Gltf { document, blob } = Gltf::open(path)?;
+    let buffer = gltf::import_buffers(&document, base, blob)?;
+    let images: Vec<Option<ImageData>> = document
+        .images()
+        .map(|image| {
+            match ImageData::from_source(image.source(), base, &buffer) {
+                Ok(data) => Some(data),
+                Err(error) => {
+                    let error = AssetError::from(error);
+                    log::warn!("skipping image {}: {error}", image.index());
+                    None
+                }
+            }
+        })
+        .collect();
 
     let mut models = Vec::new();
+    cache.forget_dropped();
+    let mut cache = Cache {
+        shared: cache,
+        images: HashMap::new(),
+        materials: HashMap::new(),
+    };
 
     for scene in document.scenes() {
         for node in scene.nodes() {
-            process_node(node, &document, &buffer, &mut models);
+            process_node(
+                node,
+                Mat4::identity(),
+                &document,
+                &buffer,
+                &images,
+                &mut cache,
+                &mut models,
+            )?;
         }
     }
 
-    models
+    let skeleton = load_skeleton(&document, &buffer);
+    let first_joint = skeleton
+        .as_ref()
+        .map_or(0, |skeleton| skeleton.joints.len());
+    let (animation, moved_by) =
+        match load_node_animation(&document, &buffer, first_joint) {
+            Some((animation, moved_by)) => (Some(animation), moved_by),
+            None => (None, Vec::new()),
+        };
+    // Meshes under an animated node follow its joint fully
+    let meshes = models
+        .into_iter()
+        .map(|(node, mut mesh)| {
+            if let Some(&Some(joint)) = moved_by.get(node) {
+                for vertex in &mut mesh.vertices {
+                    *vertex =
+                        vertex.skinned([joint, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]);
+                }
+            }
+            mesh
+        })
+        .collect();
+
+    Ok(Scene {
+        meshes,
+        skeleton,
+        animation,
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{path::PathBuf, rc::Rc};
+
     use super::*;
+    use crate::animation::{AnimationPlayer, Animator};
+
+    /// `name` in a temp directory only this test run writes to, so runs at
+    /// the same time do not overwrite each other's files
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("gltf-tests-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
 
     #[test]
     fn foo() {
-        load_glb("assets/BoxTextured.glb");
-        load_glb("assets/cube.glb");
-        load_glb("assets/ground.glb");
+        load_glb("assets/BoxTextured.glb", &mut AssetCache::default()).unwrap();
+        load_glb("assets/cube.glb", &mut AssetCache::default()).unwrap();
+        load_glb("assets/ground.glb", &mut AssetCache::default()).unwrap();
+    }
+
+    #[test]
+    fn pillar_loads_its_skeleton() {
+        let pillar =
+            load_glb("assets/pillar.gltf", &mut AssetCache::default()).unwrap();
+        let skeleton = pillar.skeleton.unwrap();
+        assert_eq!(skeleton.joints.len(), 2);
+        assert!(skeleton.clip("sway").is_some());
+        assert!(skeleton.clip("bow").is_some());
+    }
+
+    #[test]
+    fn pickup_loads_its_node_animation() {
+        let pickup =
+            load_glb("assets/pickup.gltf", &mut AssetCache::default()).unwrap();
+        let animation = pickup.animation.unwrap();
+        // Only the gem is animated, the plinth stays put
+        assert_eq!(animation.nodes.joints.len(), 1);
+        assert!(animation.nodes.clip("spin").is_some());
+        assert!(animation.nodes.clip("hop").is_some());
+    }
+
+    #[test]
+    fn missing_file() {
+        let error =
+            load_glb("assets/does_not_exist.glb", &mut AssetCache::default())
+                .err()
+                .unwrap();
+        assert!(matches!(error, AssetError::Io(_)), "{error}");
+    }
+
+    const BASE_COLOUR: &str =
+        r#"{ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } } }"#;
+    const RED_PNG: &str = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR4nGP4z8DwHwAFAAH/iZk9HQAAAABJRU5ErkJggg==";
+
+    /// A single triangle with UVs and one material, the material and the
+    /// `image` object behind texture 0 are spliced in
+    fn textured_triangle(material: &str, image: &str) -> String {
+        textured_triangle_with_sampler(material, image, "{}")
+    }
+
+    /// [`textured_triangle`] with `sampler` as texture 0's sampler
+    fn textured_triangle_with_sampler(
+        material: &str,
+        image: &str,
+        sampler: &str,
+    ) -> String {
+        let buffer = "AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA\
+                      AAAAAAAAAAAAAIA/AAAAAAAAAAAAAIA/";
+        format!(
+            r#"{{
+            "asset": {{ "version": "2.0" }},
+            "scenes": [{{ "nodes": [0] }}],
+            "nodes": [{{ "mesh": 0 }}],
+            "meshes": [{{ "primitives": [{{
+                "attributes": {{ "POSITION": 0, "TEXCOORD_0": 1 }},
+                "material": 0
+            }}] }}],
+            "materials": [{material}],
+            "textures": [{{ "source": 0, "sampler": 0 }}],
+            "samplers": [{sampler}],
+            "images": [{image}],
+            "buffers": [{{
+                "byteLength": 60,
+                "uri": "data:application/octet-stream;base64,{buffer}"
+            }}],
+            "bufferViews": [
+                {{ "buffer": 0, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 36, "byteLength": 24 }}
+            ],
+            "accessors": [
+                {{
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": 3,
+                    "type": "VEC3",
+                    "min": [0.0, 0.0, 0.0],
+                    "max": [1.0, 1.0, 0.0]
+                }},
+                {{
+                    "bufferView": 1,
+                    "componentType": 5126,
+                    "count": 3,
+                    "type": "VEC2"
+                }}
+            ]
+        }}"#
+        )
+    }
+
+    #[test]
+    fn relative_uri_texture() {
+        let dir = temp_path("relative_uri_texture");
+        std::fs::create_dir_all(dir.join("textures")).unwrap();
+        image::RgbaImage::from_pixel(2, 2, image::Rgba([0, 0, 255, 255]))
+            .save(dir.join("textures/blue.png"))
+            .unwrap();
+        let path = dir.join("triangle.gltf");
+        std::fs::write(
+            &path,
+            textured_triangle(BASE_COLOUR, r#"{ "uri": "textures/blue.png" }"#),
+        )
+        .unwrap();
+
+        let meshes =
+            load_glb(&path, &mut AssetCache::default()).unwrap().meshes;
+
+        let image = meshes[0]
+            .material
+            .base_colour_texture
+            .as_ref()
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(image.dimensions(), (2, 2));
+        assert_eq!(image.get_pixel(1, 1).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn data_uri_texture() {
+        let path = temp_path("data_uri_texture.gltf");
+        std::fs::write(
+            &path,
+            textured_triangle(
+                BASE_COLOUR,
+                &format!(r#"{{ "uri": "{RED_PNG}" }}"#),
+            ),
+        )
+        .unwrap();
+
+        let meshes =
+            load_glb(&path, &mut AssetCache::default()).unwrap().meshes;
+
+        let image = meshes[0]
+            .material
+            .base_colour_texture
+            .as_ref()
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn unreadable_textures_are_skipped() {
+        let path = temp_path("unreadable_texture.gltf");
+        let load = |image: &str| {
+            std::fs::write(&path, textured_triangle(BASE_COLOUR, image))
+                .unwrap();
+            load_glb(&path, &mut AssetCache::default())
+                .unwrap()
+                .meshes
+                .remove(0)
+                .material
+        };
+
+        let missing = load(r#"{ "uri": "textures/missing.png" }"#);
+        assert!(missing.base_colour_texture.is_none());
+
+        let garbage = load(r#"{ "uri": "data:image/png;base64,AAAA" }"#);
+        assert!(garbage.base_colour_texture.is_none());
+    }
+
+    #[test]
+    fn texture_sampler() {
+        let path = temp_path("texture_sampler.gltf");
+        let sampler = r#"{
+            "magFilter": 9728,
+            "minFilter": 9985,
+            "wrapS": 33071,
+            "wrapT": 33648
+        }"#;
+        std::fs::write(
+            &path,
+            textured_triangle_with_sampler(
+                BASE_COLOUR,
+                &format!(r#"{{ "uri": "{RED_PNG}" }}"#),
+                sampler,
+            ),
+        )
+        .unwrap();
+
+        let meshes =
+            load_glb(&path, &mut AssetCache::default()).unwrap().meshes;
+
+        assert_eq!(
+            meshes[0].material.samplers[0],
+            Sampler {
+                wrap_u: Wrap::ClampToEdge,
+                wrap_v: Wrap::MirroredRepeat,
+                mag_filter: Filter::Nearest,
+                min_filter: Filter::Linear,
+                mipmap_filter: Some(Filter::Nearest),
+            }
+        );
+    }
+
+    #[test]
+    fn each_texture_keeps_its_sampler() {
+        let path = temp_path("per_texture_samplers.gltf");
+        let material = r#"{
+            "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } },
+            "normalTexture": { "index": 1 }
+        }"#;
+        let gltf = textured_triangle_with_sampler(
+            material,
+            &format!(r#"{{ "uri": "{RED_PNG}" }}"#),
+            r#"{ "magFilter": 9728 }, { "wrapS": 33071 }"#,
+        )
+        .replace(
+            r#""textures": [{ "source": 0, "sampler": 0 }]"#,
+            r#""textures": [
+                { "source": 0, "sampler": 0 },
+                { "source": 0, "sampler": 1 }
+            ]"#,
+        );
+        std::fs::write(&path, gltf).unwrap();
+
+        let meshes =
+            load_glb(&path, &mut AssetCache::default()).unwrap().meshes;
+
+        let [base_colour, metallic_roughness, normal, ..] =
+            meshes[0].material.samplers;
+        assert_eq!(base_colour.mag_filter, Filter::Nearest);
+        assert_eq!(base_colour.wrap_u, Wrap::Repeat);
+        assert_eq!(normal.mag_filter, Filter::Linear);
+        assert_eq!(normal.wrap_u, Wrap::ClampToEdge);
+        assert_eq!(metallic_roughness, Sampler::default());
+    }
+
+    #[test]
+    fn default_texture_sampler() {
+        let path = temp_path("default_texture_sampler.gltf");
+        std::fs::write(
+            &path,
+            textured_triangle(
+                BASE_COLOUR,
+                &format!(r#"{{ "uri": "{RED_PNG}" }}"#),
+            ),
+        )
+        .unwrap();
+
+        let meshes =
+            load_glb(&path, &mut AssetCache::default()).unwrap().meshes;
+        assert_eq!(meshes[0].material.samplers, [Sampler::default(); 5]);
+    }
+
+    #[test]
+    fn shared_materials_are_loaded_once() {
+        let path = temp_path("shared_materials.gltf");
+        let gltf = textured_triangle(
+            BASE_COLOUR,
+            &format!(r#"{{ "uri": "{RED_PNG}" }}"#),
+        )
+        .replace(r#""nodes": [0]"#, r#""nodes": [0, 1]"#)
+        .replace(
+            r#""nodes": [{ "mesh": 0 }]"#,
+            r#""nodes": [{ "mesh": 0 }, { "mesh": 0 }]"#,
+        );
+        std::fs::write(&path, gltf).unwrap();
+
+        let meshes =
+            load_glb(&path, &mut AssetCache::default()).unwrap().meshes;
+
+        assert_eq!(meshes.len(), 2);
+        assert!(Rc::ptr_eq(&meshes[0].material, &meshes[1].material));
+    }
+
+    #[test]
+    fn files_share_images_and_materials() {
+        let (a, b) = (temp_path("shared_a.gltf"), temp_path("shared_b.gltf"));
+        let image = format!(r#"{{ "uri": "{RED_PNG}" }}"#);
+        let glowing = r#"{
+            "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } },
+            "emissiveFactor": [1.0, 0.0, 0.0]
+        }"#;
+        std::fs::write(&a, textured_triangle(BASE_COLOUR, &image)).unwrap();
+        std::fs::write(&b, textured_triangle(glowing, &image)).unwrap();
+
+        let mut cache = AssetCache::default();
+        let first = load_glb(&a, &mut cache).unwrap().meshes;
+        let second = load_glb(&a, &mut cache).unwrap().meshes;
+        assert!(Rc::ptr_eq(&first[0].material, &second[0].material));
+
+        // A different material still shares the image
+        let other = load_glb(&b, &mut cache).unwrap().meshes;
+        assert!(!Rc::ptr_eq(&first[0].material, &other[0].material));
+        let image = |meshes: &[Mesh]| {
+            meshes[0].material.base_colour_texture.clone().unwrap()
+        };
+        assert!(Rc::ptr_eq(&image(&first), &image(&other)));
+    }
+
+    #[test]
+    fn pbr_material() {
+        let material = r#"{
+            "pbrMetallicRoughness": {
+                "metallicFactor": 0.5,
+                "roughnessFactor": 0.25,
+                "metallicRoughnessTexture": { "index": 0 }
+            },
+            "normalTexture": { "index": 0, "scale": 2.0 },
+            "occlusionTexture": { "index": 0, "strength": 0.5 },
+            "emissiveTexture": { "index": 0 },
+            "emissiveFactor": [1.0, 0.5, 0.0]
+        }"#;
+        let path = temp_path("pbr_material.gltf");
+        std::fs::write(
+            &path,
+            textured_triangle(
+                material,
+                &format!(r#"{{ "uri": "{RED_PNG}" }}"#),
+            ),
+        )
+        .unwrap();
+
+        let material =
+            &load_glb(&path, &mut AssetCache::default()).unwrap().meshes[0]
+                .material;
+
+        assert_eq!(material.metallic, 0.5);
+        assert_eq!(material.roughness, 0.25);
+        assert_eq!(material.normal_scale, 2.0);
+        assert_eq!(material.occlusion_strength, 0.5);
+        assert_eq!(material.emissive, [1.0, 0.5, 0.0]);
+        let textures = material.textures().map(|texture| texture.is_some());
+        assert_eq!(textures, [false, true, true, true, true]);
+    }
+
+    #[test]
+    fn alpha_modes() {
+        let path = temp_path("alpha_modes.gltf");
+        let load = |material: &str| {
+            std::fs::write(
+                &path,
+                textured_triangle(
+                    material,
+                    &format!(r#"{{ "uri": "{RED_PNG}" }}"#),
+                ),
+            )
+            .unwrap();
+            load_glb(&path, &mut AssetCache::default())
+                .unwrap()
+                .meshes
+                .remove(0)
+                .material
+        };
+
+        let opaque = load("{}");
+        assert_eq!(opaque.alpha_mode, AlphaMode::Opaque);
+        assert_eq!(opaque.alpha_cutoff, 0.5);
+
+        let mask = load(r#"{ "alphaMode": "MASK", "alphaCutoff": 0.25 }"#);
+        assert_eq!(mask.alpha_mode, AlphaMode::Mask);
+        assert_eq!(mask.alpha_cutoff, 0.25);
+
+        let blend = load(r#"{ "alphaMode": "BLEND" }"#);
+        assert_eq!(blend.alpha_mode, AlphaMode::Blend);
+    }
+
+    #[test]
+    fn node_transforms_are_accumulated() {
+        // The parent moves up 2 and the child is mirrored in x then doubled
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [
+                { "translation": [0.0, 2.0, 0.0], "children": [1] },
+                { "scale": [-2.0, 2.0, 2.0], "mesh": 0 }
+            ],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "buffers": [{
+                "byteLength": 36,
+                "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"
+            }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+            "accessors": [{
+                "bufferView": 0,
+                "componentType": 5126,
+                "count": 3,
+                "type": "VEC3",
+                "min": [0.0, 0.0, 0.0],
+                "max": [1.0, 1.0, 0.0]
+            }]
+        }"#;
+        let path = temp_path("node_transforms.gltf");
+        std::fs::write(&path, gltf).unwrap();
+
+        let meshes =
+            load_glb(&path, &mut AssetCache::default()).unwrap().meshes;
+
+        let positions: Vec<[f32; 3]> = meshes[0]
+            .vertices
+            .iter()
+            .map(|vertex| [vertex.vec3.x, vertex.vec3.y, vertex.vec3.z])
+            .collect();
+        assert_eq!(
+            positions,
+            [[0.0, 2.0, 0.0], [-2.0, 2.0, 0.0], [0.0, 4.0, 0.0]]
+        );
+        // Mirrored, so the winding is flipped to keep the front face at +z
+        assert_eq!(meshes[0].indices, [0, 2, 1]);
+        for vertex in &meshes[0].vertices {
+            assert_eq!(vertex.normal.z, 1.0);
+        }
+    }
+
+    #[test]
+    fn positions_only_triangle() {
+        // One triangle in the XY plane with no indices, normals or UVs
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "buffers": [{
+                "byteLength": 36,
+                "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"
+            }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+            "accessors": [{
+                "bufferView": 0,
+                "componentType": 5126,
+                "count": 3,
+                "type": "VEC3",
+                "min": [0.0, 0.0, 0.0],
+                "max": [1.0, 1.0, 0.0]
+            }]
+        }"#;
+        let path = temp_path("positions_only_triangle.gltf");
+        std::fs::write(&path, gltf).unwrap();
+
+        let meshes =
+            load_glb(&path, &mut AssetCache::default()).unwrap().meshes;
+
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].indices, [0, 1, 2]);
+        assert!(meshes[0].material.base_colour_texture.is_none());
+        for vertex in &meshes[0].vertices {
+            assert_eq!(vertex.normal.z, 1.0);
+        }
+    }
+
+    #[test]
+    fn mismatched_attribute_counts() {
+        // Three positions but only the first two of them as normals
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{ "primitives": [{
+                "attributes": { "POSITION": 0, "NORMAL": 1 }
+            }] }],
+            "buffers": [{
+                "byteLength": 36,
+                "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"
+            }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+            "accessors": [
+                {
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": 3,
+                    "type": "VEC3",
+                    "min": [0.0, 0.0, 0.0],
+                    "max": [1.0, 1.0, 0.0]
+                },
+                {
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": 2,
+                    "type": "VEC3"
+                }
+            ]
+        }"#;
+        let path = temp_path("mismatched_attributes.gltf");
+        std::fs::write(&path, gltf).unwrap();
+
+        let error = load_glb(&path, &mut AssetCache::default()).err().unwrap();
+        assert!(
+            matches!(error, AssetError::MismatchedAttribute("NORMAL")),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn skins_and_clips() {
+        // A two joint chain standing on the origin, the top vertex follows
+        // the upper joint. "lift" raises the upper joint by one
+        let buffer = "AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAAEAAAAAAAAAAAAAA\
+                      AAAAAAAAAAAAAAEAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA\
+                      AAAAAAAAAAAAAIA/AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAA\
+                      AAAAAIA/AAAAAAAAAAAAAAAAAAAAAAAAgD8AAAAAAAAAAAAAgL8AAAAA\
+                      AACAPwAAgD8AAAAAAAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAA\
+                      AAAAAIA/AAAAAAAAAAAAAADAAAAAAAAAgD8AAAAAAACAPwAAAAAAAIA/\
+                      AAAAAAAAAAAAAABAAAAAAA==";
+        let gltf = format!(
+            r#"{{
+            "asset": {{ "version": "2.0" }},
+            "scenes": [{{ "nodes": [0, 1] }}],
+            "nodes": [
+                {{ "mesh": 0, "skin": 0, "translation": [5.0, 0.0, 0.0] }},
+                {{ "translation": [0.0, 1.0, 0.0], "children": [2] }},
+                {{ "translation": [0.0, 1.0, 0.0] }}
+            ],
+            "skins": [{{ "joints": [1, 2], "inverseBindMatrices": 3 }}],
+            "animations": [{{
+                "name": "lift",
+                "channels": [{{
+                    "sampler": 0,
+                    "target": {{ "node": 2, "path": "translation" }}
+                }}],
+                "samplers": [{{ "input": 4, "output": 5 }}]
+            }}],
+            "meshes": [{{ "primitives": [{{ "attributes": {{
+                "POSITION": 0, "JOINTS_0": 1, "WEIGHTS_0": 2
+            }} }}] }}],
+            "buffers": [{{
+                "byteLength": 268,
+                "uri": "data:application/octet-stream;base64,{buffer}"
+            }}],
+            "bufferViews": [
+                {{ "buffer": 0, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 36, "byteLength": 24 }},
+                {{ "buffer": 0, "byteOffset": 60, "byteLength": 48 }},
+                {{ "buffer": 0, "byteOffset": 108, "byteLength": 128 }},
+                {{ "buffer": 0, "byteOffset": 236, "byteLength": 8 }},
+                {{ "buffer": 0, "byteOffset": 244, "byteLength": 24 }}
+            ],
+            "accessors": [
+                {{
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": 3,
+                    "type": "VEC3",
+                    "min": [0.0, 0.0, 0.0],
+                    "max": [1.0, 2.0, 0.0]
+                }},
+                {{
+                    "bufferView": 1,
+                    "componentType": 5123,
+                    "count": 3,
+                    "type": "VEC4"
+                }},
+                {{
+                    "bufferView": 2,
+                    "componentType": 5126,
+                    "count": 3,
+                    "type": "VEC4"
+                }},
+                {{
+                    "bufferView": 3,
+                    "componentType": 5126,
+                    "count": 2,
+                    "type": "MAT4"
+                }},
+                {{
+                    "bufferView": 4,
+                    "componentType": 5126,
+                    "count": 2,
+                    "type": "SCALAR",
+                    "min": [0.0],
+                    "max": [1.0]
+                }},
+                {{
+                    "bufferView": 5,
+                    "componentType": 5126,
+                    "count": 2,
+                    "type": "VEC3"
+                }}
+            ]
+        }}"#
+        );
+        let path = temp_path("skins_and_clips.gltf");
+        std::fs::write(&path, gltf).unwrap();
+
+        let scene = load_glb(&path, &mut AssetCache::default()).unwrap();
+
+        // The skinned node's own translation is ignored
+        let vertices = &scene.meshes[0].vertices;
+        assert_eq!(vertices[1].vec3, Vec3::x());
+        assert_eq!(vertices[2].joints, [1, 0, 0, 0]);
+        assert_eq!(vertices[2].weights, [1.0, 0.0, 0.0, 0.0]);
+
+        let skeleton = scene.skeleton.unwrap();
+        assert_eq!(skeleton.joints.len(), 2);
+        assert_eq!(skeleton.joints[0].parent, None);
+        assert_eq!(skeleton.joints[1].parent, Some(0));
+        assert_eq!(skeleton.clips[0].name, "lift");
+        assert_eq!(skeleton.clips[0].duration(), 1.0);
+
+        let mut animator = Animator::new(Rc::new(skeleton));
+        assert_eq!(animator.joint_matrices(), [Mat4::identity(); 2]);
+        animator.play("lift", false).unwrap();
+        animator.advance(1.0);
+        assert_eq!(animator.joint_matrices()[0], Mat4::identity());
+        assert_eq!(
+            animator.joint_matrices()[1],
+            Mat4::from_translation(Vec3::new(0.0, 1.0, 0.0))
+        );
+    }
+
+    #[test]
+    fn rigid_animations() {
+        // A triangle on a root 2 along z, "open" swings it a quarter turn
+        // about y in one step. A still node 1 along x holds a second
+        // triangle that "open" lifts by one, a third triangle never moves
+        let gltf = r#"{
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0, 3] }],
+            "nodes": [
+                { "mesh": 0, "translation": [0.0, 0.0, 2.0], "children": [1] },
+                { "translation": [1.0, 0.0, 0.0], "children": [2] },
+                { "mesh": 0 },
+                { "mesh": 0 }
+            ],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "animations": [{
+                "name": "open",
+                "channels": [
+                    {
+                        "sampler": 0,
+                        "target": { "node": 0, "path": "rotation" }
+                    },
+                    {
+                        "sampler": 1,
+                        "target": { "node": 2, "path": "translation" }
+                    }
+                ],
+                "samplers": [
+                    { "input": 1, "output": 2, "interpolation": "STEP" },
+                    { "input": 1, "output": 3, "interpolation": "STEP" }
+                ]
+            }],
+            "buffers": [{
+                "byteLength": 100,
+                "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAAAAAAAgD8AAAAAAAAAAAAAAAAAAIA/AAAAAPMENT8AAAAA8wQ1PwAAAAAAAAAAAAAAAAAAAAAAAIA/AAAAAA=="
+            }],
+            "bufferViews": [
+                { "buffer": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 8 },
+                { "buffer": 0, "byteOffset": 44, "byteLength": 32 },
+                { "buffer": 0, "byteOffset": 76, "byteLength": 24 }
+            ],
+            "accessors": [
+                {
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": 3,
+                    "type": "VEC3",
+                    "min": [0.0, 0.0, 0.0],
+                    "max": [1.0, 1.0, 0.0]
+                },
+                {
+                    "bufferView": 1,
+                    "componentType": 5126,
+                    "count": 2,
+                    "type": "SCALAR",
+                    "min": [0.0],
+                    "max": [1.0]
+                },
+                {
+                    "bufferView": 2,
+                    "componentType": 5126,
+                    "count": 2,
+                    "type": "VEC4"
+                },
+                {
+                    "bufferView": 3,
+                    "componentType": 5126,
+                    "count": 2,
+                    "type": "VEC3"
+                }
+            ]
+        }"#;
+        let path = temp_path("rigid_animations.gltf");
+        std::fs::write(&path, gltf).unwrap();
+
+        let scene = load_glb(&path, &mut AssetCache::default()).unwrap();
+        assert!(scene.skeleton.is_none());
+        // Children are loaded before their parents
+        let [lifted, root, still] = &scene.meshes[..] else {
+            panic!("expected three meshes");
+        };
+        let corner = |mesh: &Mesh| mesh.vertices[1];
+        assert_eq!(corner(root).vec3, Vec3::new(1.0, 0.0, 2.0));
+        assert_eq!(corner(root).joints, [0, 0, 0, 0]);
+        assert_eq!(corner(root).weights, [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(corner(lifted).vec3, Vec3::new(2.0, 0.0, 2.0));
+        assert_eq!(corner(lifted).joints, [1, 0, 0, 0]);
+        assert_eq!(corner(still).weights, [0.0; 4]);
+
+        let animation = scene.animation.unwrap();
+        let nodes = &animation.nodes;
+        assert_eq!(nodes.joints.len(), 2);
+        assert_eq!(nodes.joints[1].parent, Some(0));
+        assert_eq!(
+            nodes.joints[1].parent_transform,
+            Mat4::from_translation(Vec3::x())
+        );
+        assert_eq!(nodes.clips[0].name, "open");
+
+        let mut player = AnimationPlayer::new(Rc::new(animation));
+        player.play("open", false).unwrap();
+        player.advance(0.5);
+        assert_eq!(player.node_matrices(), [Mat4::identity(); 2]);
+
+        // Turned about the root, not the origin, and the lifted triangle
+        // turns along with it
+        player.advance(0.5);
+        let moved = |joint: usize, point: Vec3| {
+            player.node_matrices()[joint].transform_point(point)
+        };
+        let root_corner = moved(0, corner(root).vec3);
+        assert!(
+            (root_corner - Vec3::new(0.0, 0.0, 1.0)).len() < 1e-5,
+            "{root_corner:?}"
+        );
+        let lifted_corner = moved(1, corner(lifted).vec3);
+        assert!(
+            (lifted_corner - Vec3::new(0.0, 1.0, 0.0)).len() < 1e-5,
+            "{lifted_corner:?}"
+        );
     }
 }
@@ -1,26 +1,106 @@
 mod gltf;
 
-use image::DynamicImage;
+use std::{fmt, io, path::Path, rc::Rc};
 
-pub use gltf::load_glb;
+use image::{DynamicImage, ImageError};
 
-use super::{Vertex, gpu::MaterialUniform};
+pub use gltf::{AssetCache, load_glb};
 
-impl From<&Material> for MaterialUniform {
-    fn from(m: &Material) -> Self {
-        MaterialUniform::new(
-            m.base_colour,
-            m.metallic,
-            m.roughness,
-            m.image.is_some(),
-        )
-    }
-}
+use super::Vertex;
+use crate::{
+    animation::{NodeAnimation, Skeleton},
+    maths::Aabb,
+};
+
+/// glTF metallic-roughness material, each factor is multiplied with its
+/// texture when there is one
 pub struct Material {
     pub base_colour: [f32; 4],
     pub metallic: f32,
     pub roughness: f32,
-    pub image: Option<DynamicImage>,
+    pub emissive: [f32; 3],
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    /// sRGB
+    pub base_colour_texture: Option<Rc<DynamicImage>>,
+    /// Linear, roughness in green and metallic in blue
+    pub metallic_roughness_texture: Option<Rc<DynamicImage>>,
+    /// Linear, tangent space
+    pub normal_texture: Option<Rc<DynamicImage>>,
+    /// Linear, occlusion in red
+    pub occlusion_texture: Option<Rc<DynamicImage>>,
+    /// sRGB
+    pub emissive_texture: Option<Rc<DynamicImage>>,
+    /// How each texture is sampled, in the order of [`Material::textures`]
+    pub samplers: [Sampler; 5],
+    pub alpha_mode: AlphaMode,
+    /// Masked materials are transparent where alpha is below this
+    pub alpha_cutoff: f32,
+}
+
+/// What the base colour's alpha means, glTF's `alphaMode`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AlphaMode {
+    /// Alpha is ignored
+    #[default]
+    Opaque,
+    /// Fully transparent below the cutoff and opaque above it
+    Mask,
+    /// Blended over whatever is behind
+    Blend,
+}
+
+/// How texture coordinates outside 0..1 are handled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Wrap {
+    #[default]
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Nearest,
+    #[default]
+    Linear,
+}
+
+/// glTF texture sampler, anything the file leaves out is linear and repeats
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Sampler {
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    /// Between mip levels, [`None`] only samples the full size image
+    pub mipmap_filter: Option<Filter>,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self {
+            wrap_u: Wrap::default(),
+            wrap_v: Wrap::default(),
+            mag_filter: Filter::default(),
+            min_filter: Filter::default(),
+            mipmap_filter: Some(Filter::default()),
+        }
+    }
+}
+
+impl Material {
+    /// Base colour, metallic-roughness, normal, occlusion then emissive, the
+    /// order the shader expects them in
+    pub fn textures(&self) -> [Option<&Rc<DynamicImage>>; 5] {
+        [
+            self.base_colour_texture.as_ref(),
+            self.metallic_roughness_texture.as_ref(),
+            self.normal_texture.as_ref(),
+            self.occlusion_texture.as_ref(),
+            self.emissive_texture.as_ref(),
+        ]
+    }
 }
 
 impl Default for Material {
@@ -29,7 +109,17 @@ impl Default for Material {
             base_colour: [1.0, 1.0, 1.0, 1.0],
             metallic: 0.0,
             roughness: 1.0,
-            image: None,
+            emissive: [0.0, 0.0, 0.0],
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            base_colour_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
+            samplers: [Sampler::default(); 5],
+            alpha_mode: AlphaMode::default(),
+            alpha_cutoff: 0.5,
         }
     }
 }
@@ -37,18 +127,122 @@ impl Default for Material {
 pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
-    pub material: Material,
+    /// Shared by every primitive using the same glTF material
+    pub material: Rc<Material>,
+    /// Bounds of `vertices`, for culling
+    pub bounds: Aabb,
 }
 impl Mesh {
     pub fn new(
         vertices: Vec<Vertex>,
         indices: Vec<u32>,
-        material: Material,
+        material: Rc<Material>,
     ) -> Self {
         Self {
+            bounds: Aabb::from_points(
+                vertices.iter().map(|vertex| vertex.vec3),
+            ),
             vertices,
             indices,
             material,
         }
     }
 }
+
+/// Every primitive loaded from one glTF file, registered under `name`
+pub struct Model {
+    pub name: String,
+    pub meshes: Vec<Mesh>,
+    /// Joints and clips for skinned meshes
+    pub skeleton: Option<Skeleton>,
+    /// Clips moving the nodes of a rigid model
+    pub animation: Option<NodeAnimation>,
+}
+impl Model {
+    /// Load a glTF file named after its file stem, `assets/cube.glb` is `cube`.
+    /// Images and materials already in `cache` are shared rather than copied
+    pub fn load(
+        path: impl AsRef<Path>,
+        cache: &mut AssetCache,
+    ) -> Result<Self, AssetError> {
+        let path = path.as_ref();
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        Self::load_as(name, path, cache)
+    }
+    pub fn load_as(
+        name: impl Into<String>,
+        path: impl AsRef<Path>,
+        cache: &mut AssetCache,
+    ) -> Result<Self, AssetError> {
+        let scene = load_glb(path, cache)?;
+        Ok(Self {
+            name: name.into(),
+            meshes: scene.meshes,
+            skeleton: scene.skeleton,
+            animation: scene.animation,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum AssetError {
+    Io(io::Error),
+    /// The file is not valid glTF or references data we could not read
+    Gltf(::gltf::Error),
+    /// A primitive is missing an attribute we have no fallback for
+    MissingAttribute(&'static str),
+    /// A primitive has a different number of these than positions
+    MismatchedAttribute(&'static str),
+    /// A texture is stored in a format we cannot decode
+    UnsupportedImage(String),
+    Image(ImageError),
+    /// Nothing has been registered under this name
+    UnknownModel(String),
+    /// The skeleton has no animation clip with this name
+    UnknownClip(String),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "io error: {error}"),
+            Self::Gltf(error) => write!(f, "gltf error: {error}"),
+            Self::MissingAttribute(attribute) => {
+                write!(f, "primitive has no {attribute} attribute")
+            }
+            Self::MismatchedAttribute(attribute) => {
+                write!(f, "primitive's {attribute} count differs from POSITION")
+            }
+            Self::UnsupportedImage(format) => {
+                write!(f, "unsupported image format {format:?}")
+            }
+            Self::Image(error) => write!(f, "image error: {error}"),
+            Self::UnknownModel(name) => write!(f, "no model named {name:?}"),
+            Self::UnknownClip(name) => write!(f, "no clip named {name:?}"),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Gltf(error) => Some(error),
+            Self::Image(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<::gltf::Error> for AssetError {
+    fn from(error: ::gltf::Error) -> Self {
+        match error {
+            ::gltf::Error::Io(error) => Self::Io(error),
+            ::gltf::Error::Image(error) => Self::Image(error),
+            ::gltf::Error::UnsupportedImageFormat(image) => {
+                Self::UnsupportedImage(format!("{:?}", image.color()))
+            }
+            error => Self::Gltf(error),
+        }
+    }
+}
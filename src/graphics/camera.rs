@@ -1,8 +1,9 @@
 use std::f32::consts::PI;
 
+use bytemuck::{Pod, Zeroable};
 use winit::dpi::PhysicalSize;
 
-use crate::maths::{Mat3, Mat4, Vec3, Vec4};
+use crate::maths::{Aabb, Mat3, Mat4, Vec3, Vec4};
 
 #[derive(Debug)]
 pub struct Camera {
@@ -30,9 +31,6 @@ impl Camera {
             far: 1000.0,
         }
     }
-    pub fn set_target(&mut self, target: Vec3) {
-        self.target = target;
-    }
     pub fn set_position(&mut self, position: Vec3) {
         self.position = position;
     }
@@ -42,11 +40,6 @@ impl Camera {
     pub fn target(&self) -> Vec3 {
         self.target
     }
-    pub fn follow(&mut self, target: Vec3) {
-        let offset = target - self.target;
-        self.target = target;
-        self.position = self.position + offset;
-    }
     pub fn rotate_x(&mut self, delta_time: f32, theta: f32) {
         self.position = Mat3::rotation_x(theta * delta_time) * self.position;
     }
@@ -71,39 +64,162 @@ impl Camera {
         self.position += delta;
         self.target += delta;
     }
-    fn view_rh(&self) -> Mat4 {
-        let forward = (self.target - self.position).normalise();
-        let right = forward.cross(&self.up).normalise();
-        let up = right.cross(&forward).normalise();
+    pub fn view_perspective_rh(&self) -> Mat4 {
+        Mat4::look_at_rh(self.position, self.target, self.up)
+            * Mat4::perspective_rh(self.fovy, self.aspect, self.near, self.far)
+    }
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_perspective(&self.view_perspective_rh())
+    }
+
+    pub fn set_aspect_ratio(&mut self, size: &PhysicalSize<u32>) {
+        self.aspect = size.width as f32 / size.height as f32
+    }
+}
 
-        let projection_x = -right.dot(&self.position);
-        let projection_y = -up.dot(&self.position);
-        let projection_z = forward.dot(&self.position);
+/// The volume a camera can see, bounded by six planes
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    /// Normal in xyz pointing inside and distance in w, a point p is inside
+    /// a plane when dot(normal, p) + w >= 0
+    planes: [Vec4; 6],
+}
 
-        Mat4 {
-            x: Vec4::new(right.x, up.x, -forward.x, 0.0),
-            y: Vec4::new(right.y, up.y, -forward.y, 0.0),
-            z: Vec4::new(right.z, up.z, -forward.z, 0.0),
-            w: Vec4::new(projection_x, projection_y, projection_z, 1.0),
+impl Frustum {
+    /// Pull the planes out of the rows of a view perspective matrix
+    pub fn from_view_perspective(matrix: &Mat4) -> Self {
+        let rows = matrix.transpose();
+        let plane = |row: Vec4, sign: f32| {
+            Vec4::new(
+                rows.w.x + sign * row.x,
+                rows.w.y + sign * row.y,
+                rows.w.z + sign * row.z,
+                rows.w.w + sign * row.w,
+            )
+        };
+        Self {
+            planes: [
+                plane(rows.x, 1.0),
+                plane(rows.x, -1.0),
+                plane(rows.y, 1.0),
+                plane(rows.y, -1.0),
+                // Depth runs 0..1, so the near plane is z >= 0 alone
+                rows.z,
+                plane(rows.z, -1.0),
+            ],
         }
     }
-    fn perspective_rh(&self) -> Mat4 {
-        let tan_half_fov = 1.0 / (self.fovy / 2.0).tan();
-        let range = self.far - self.near;
-        let depth = -(self.far + self.near) / range;
-        let project = -(2.0 * self.far * self.near) / range;
-        Mat4 {
-            x: Vec4::new(tan_half_fov / self.aspect, 0.0, 0.0, 0.0),
-            y: Vec4::new(0.0, tan_half_fov, 0.0, 0.0),
-            z: Vec4::new(0.0, 0.0, depth, -1.0),
-            w: Vec4::new(0.0, 0.0, project, 0.0),
+    /// Whether any of `aabb` could be visible. Boxes near the corners of the
+    /// frustum can pass without being inside, which only costs a draw
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane's normal
+            let corner = Vec3::new(
+                if plane.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            plane.x * corner.x
+                + plane.y * corner.y
+                + plane.z * corner.z
+                + plane.w
+                >= 0.0
+        })
+    }
+}
+
+/// The camera as the shaders see it
+#[derive(Zeroable, Pod, Copy, Clone)]
+#[repr(C)]
+pub struct CameraUniform {
+    view_perspective: Mat4,
+    position: Vec3,
+    _padding: [u8; 4],
+}
+
+impl From<&Camera> for CameraUniform {
+    fn from(camera: &Camera) -> Self {
+        Self {
+            view_perspective: camera.view_perspective_rh(),
+            position: camera.position,
+            _padding: Default::default(),
         }
     }
-    pub fn view_perspective_rh(&self) -> Mat4 {
-        self.view_rh() * self.perspective_rh()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube_at(centre: Vec3) -> Aabb {
+        Aabb::from_points([centre - Vec3::xyz(0.1), centre + Vec3::xyz(0.1)])
     }
 
-    pub fn set_aspect_ratio(&mut self, size: &PhysicalSize<u32>) {
-        self.aspect = size.width as f32 / size.height as f32
+    #[test]
+    fn frustum_culls_what_the_camera_cannot_see() {
+        let camera = Camera::new(&PhysicalSize::new(800, 600));
+        let frustum = camera.frustum();
+        let forward = (camera.target() - camera.position()).normalise();
+
+        assert!(frustum.intersects(&cube_at(camera.target())));
+        assert!(!frustum.intersects(&cube_at(camera.position() - forward)));
+        assert!(
+            !frustum.intersects(&cube_at(camera.target() + forward * 2000.0))
+        );
+        assert!(!frustum.intersects(&cube_at(Vec3::new(0.0, 100.0, 0.0))));
+    }
+
+    #[test]
+    fn frustum_near_plane_is_at_the_near_distance() {
+        let camera = Camera::new(&PhysicalSize::new(800, 600));
+        let frustum = camera.frustum();
+        let forward = (camera.target() - camera.position()).normalise();
+        let speck = |distance: f32| {
+            let centre = camera.position() + forward * distance;
+            Aabb::from_points([
+                centre - Vec3::xyz(0.01),
+                centre + Vec3::xyz(0.01),
+            ])
+        };
+
+        assert!(!frustum.intersects(&speck(camera.near / 2.0)));
+        assert!(frustum.intersects(&speck(camera.near * 2.0)));
+    }
+
+    #[test]
+    fn frustum_keeps_boxes_straddling_a_plane() {
+        let camera = Camera::new(&PhysicalSize::new(800, 600));
+        let big = Aabb::from_points([Vec3::xyz(-100.0), Vec3::xyz(100.0)]);
+        assert!(camera.frustum().intersects(&big));
+    }
+
+    #[test]
+    fn near_and_far_map_to_the_ends_of_depth() {
+        let camera = Camera::new(&PhysicalSize::new(800, 600));
+        let projection = Mat4::perspective_rh(
+            camera.fovy,
+            camera.aspect,
+            camera.near,
+            camera.far,
+        );
+        // Depth of a point `distance` straight ahead, the view looks down -z
+        let depth = |distance: f32| {
+            let clip = projection * Vec4::new(0.0, 0.0, -distance, 1.0);
+            clip.z / clip.w
+        };
+        assert!(depth(camera.near).abs() < 1e-5);
+        assert!((depth(camera.far) - 1.0).abs() < 1e-5);
     }
 }
@@ -1,31 +1,192 @@
-use std::{num::NonZeroU64, rc::Rc};
+use std::{
+    collections::HashMap, fmt, fs, num::NonZeroU64, ops::Range, path::Path,
+    rc::Rc,
+};
 
 use bytemuck::bytes_of;
+use image::{
+    DynamicImage, RgbaImage,
+    imageops::{self, FilterType},
+};
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt as _},
     *,
 };
 
 use crate::{
+    animation::{NodeAnimation, Skeleton},
     game::Entity,
-    maths::{Mat4, Vec3},
+    maths::{Aabb, Mat4, Vec2, Vec3, Vec4},
+};
+
+use super::{
+    AssetError, Camera, Light, Lights,
+    assets::{self, AlphaMode},
+    camera::{CameraUniform, Frustum},
+    post::{
+        HDR_FORMAT, PostProcess, PostStage, Tonemapper, create_hdr_texture,
+    },
+    watch::FileWatch,
 };
 
-use super::{Camera, Light, MeshId, assets};
+/// The main shader on disk, read instead of the embedded copy once
+/// [`Gpu::watch_shaders`] is called. Absolute so it is found whichever
+/// directory the game runs from
+const SHADER_PATH: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/shader.wgsl");
+const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+const OFFSCREEN_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+const INITIAL_LIGHT_CAPACITY: usize = 16;
+const INITIAL_INSTANCE_CAPACITY: usize = 256;
+const INITIAL_JOINT_CAPACITY: usize = 64;
+/// Anisotropic filtering for samplers that filter linearly everywhere
+const MAX_ANISOTROPY: u16 = 16;
+/// A pale sky blue, linear
+const DEFAULT_CLEAR_COLOUR: Color = Color {
+    r: 0.35,
+    g: 0.55,
+    b: 0.85,
+    a: 1.0,
+};
+/// Used when the adapter supports it, WebGPU guarantees 4 for most formats
+const DEFAULT_SAMPLE_COUNT: u32 = 4;
+const SHADOW_MAP_SIZE: u32 = 2048;
+/// How far the shadow map reaches from its focus in world units
+const SHADOW_RADIUS: f32 = 4.0;
+/// Bindings in the texture group for each of [`assets::Material::textures`]
+const MATERIAL_TEXTURE_BINDINGS: [u32; 5] = [1, 3, 4, 5, 6];
+/// Bindings in the texture group for the sampler of each texture
+const MATERIAL_SAMPLER_BINDINGS: [u32; 5] = [2, 7, 8, 9, 10];
+/// Colour textures are sRGB, the rest hold linear data
+const MATERIAL_TEXTURE_FORMATS: [TextureFormat; 5] = [
+    TextureFormat::Rgba8UnormSrgb,
+    TextureFormat::Rgba8Unorm,
+    TextureFormat::Rgba8Unorm,
+    TextureFormat::Rgba8Unorm,
+    TextureFormat::Rgba8UnormSrgb,
+];
+
+/// Where the main pass ends up, either a window's swapchain or a texture we
+/// can read back on the CPU
+enum RenderTarget {
+    Surface {
+        surface: Surface<'static>,
+        config: SurfaceConfiguration,
+    },
+    Offscreen {
+        texture: Texture,
+    },
+}
 
 pub struct Gpu {
-    meshes: Vec<Rc<Mesh>>,
-    surface: Surface<'static>,
-    surface_config: SurfaceConfiguration,
+    models: Vec<Model>,
+    model_names: HashMap<String, ModelId>,
+    target: RenderTarget,
     device: Device,
     queue: Queue,
+    /// Draws opaque and masked meshes
     render_pipeline: RenderPipeline,
+    /// Draws blended meshes over the opaque ones, back to front
+    blend_pipeline: RenderPipeline,
+    /// Kept to rebuild the pipelines when the sample count changes
+    pipeline_layout: PipelineLayout,
+    shader: ShaderModule,
+    /// Set in dev mode to rebuild the pipelines when the shader changes
+    shader_watch: Option<FileWatch>,
+    /// Samples per pixel, 1 turns MSAA off
+    sample_count: u32,
+    /// Which of 1, 2, 4 and 8 samples the adapter can render with
+    supported_sample_counts: Vec<u32>,
+    /// Multisampled colour target resolved into `hdr_view`, [`None`] without
+    /// MSAA
+    msaa_view: Option<TextureView>,
+    /// The lit frame before post processing
+    hdr_view: TextureView,
+    /// Turns `hdr_view` into the frame that is shown
+    post: PostProcess,
     texture_layout: BindGroupLayout,
-    transform_layout: BindGroupLayout,
     camera_bind_group: BindGroup,
     camera_buffer: Buffer,
+    light_layout: BindGroupLayout,
     light_bind_group: BindGroup,
-    _light_buffer: Buffer,
+    light_buffer: Buffer,
+    /// How many lights fit in `light_buffer` before it has to grow
+    light_capacity: usize,
+    /// Every entity transform for the frame, as per instance vertex data
+    instance_buffer: Buffer,
+    /// How many transforms fit in `instance_buffer` before it has to grow
+    instance_capacity: usize,
+    joint_layout: BindGroupLayout,
+    joint_bind_group: BindGroup,
+    /// Joint matrices of every skinned entity for the frame, one after the
+    /// other
+    joint_buffer: Buffer,
+    /// How many matrices fit in `joint_buffer` before it has to grow
+    joint_capacity: usize,
+    shadow_map: ShadowMap,
+    depth_view: TextureView,
+    /// What shows where nothing is drawn
+    clear_colour: Color,
+    cache: ResourceCache,
+    /// What the camera sees, entities outside it are not drawn
+    frustum: Frustum,
+    /// What the shadow caster sees, entities outside it cast no shadow.
+    /// [`None`] without a shadow caster
+    shadow_frustum: Option<Frustum>,
+    /// Where blended meshes are sorted back to front from
+    camera_position: Vec3,
+    cull_stats: CullStats,
+    /// Whether entities sharing a model are drawn in one instanced call,
+    /// turned off to compare against one draw per entity
+    batching: bool,
+}
+
+/// GPU copies of assets shared between meshes, keyed by the address of the
+/// asset so anything loaded once is uploaded once. Entries hold on to their
+/// asset so the address cannot be reused by another
+#[derive(Default)]
+struct ResourceCache {
+    materials:
+        HashMap<*const assets::Material, (Rc<assets::Material>, BindGroup)>,
+    textures: HashMap<
+        (*const DynamicImage, TextureFormat),
+        (Rc<DynamicImage>, TextureView),
+    >,
+    /// Stand ins for missing textures
+    placeholders: HashMap<TextureFormat, TextureView>,
+    samplers: HashMap<assets::Sampler, Sampler>,
+}
+
+impl ResourceCache {
+    /// Drop materials no mesh draws with any more, then the textures only
+    /// those materials used. The cache's own [`Rc`] is the last one left
+    fn evict_unused(&mut self) {
+        self.materials
+            .retain(|_, (material, _)| Rc::strong_count(material) > 1);
+        self.textures
+            .retain(|_, (image, _)| Rc::strong_count(image) > 1);
+    }
+}
+
+/// How many entities the last frame drew and how many were culled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub drawn: usize,
+    pub culled: usize,
+}
+
+/// Depth of the scene as seen from the shadow casting light, the main pass
+/// compares against it to find what is in shadow
+struct ShadowMap {
+    pipeline: RenderPipeline,
+    /// Also binds the material to discard what the alpha cutoff removes
+    masked_pipeline: RenderPipeline,
+    view: TextureView,
+    sampler: Sampler,
+    /// Holds a [`ShadowUniform`]
+    buffer: Buffer,
+    /// Binds `buffer` for the shadow pass
+    bind_group: BindGroup,
 }
 
 impl Gpu {
@@ -34,28 +195,103 @@ impl Gpu {
         window_width: u32,
         window_height: u32,
         camera: &Camera,
-        light: &Light,
     ) -> Self {
         let instance =
             Instance::new(&InstanceDescriptor::from_env_or_default());
         let surface = instance.create_surface(window).unwrap();
 
         let (adapter, device, queue) =
-            pollster::block_on(init_wgpu(&instance, &surface));
+            pollster::block_on(init_wgpu(&instance, Some(&surface), false))
+                .unwrap();
 
-        let surface_config = surface
+        let config = surface
             .get_default_config(&adapter, window_width, window_height)
             .unwrap();
-        surface.configure(&device, &surface_config);
+        surface.configure(&device, &config);
+
+        log::info!("{:#?}", adapter.get_info());
+
+        let format = config.format;
+        Self::with_target(
+            &adapter,
+            device,
+            queue,
+            RenderTarget::Surface { surface, config },
+            format,
+            camera,
+        )
+    }
+
+    /// Render into an offscreen texture instead of a window, use
+    /// [`Gpu::render_to_image`] to read frames back. Prefers a hardware
+    /// adapter, falls back to a software one and returns [`None`] when
+    /// neither is available.
+    pub fn headless(width: u32, height: u32, camera: &Camera) -> Option<Self> {
+        let instance =
+            Instance::new(&InstanceDescriptor::from_env_or_default());
+
+        let (adapter, device, queue) = pollster::block_on(init_wgpu(
+            &instance, None, false,
+        ))
+        .or_else(|| pollster::block_on(init_wgpu(&instance, None, true)))?;
+
+        log::info!("{:#?}", adapter.get_info());
+
+        let texture = create_offscreen_texture(&device, width, height);
+        Some(Self::with_target(
+            &adapter,
+            device,
+            queue,
+            RenderTarget::Offscreen { texture },
+            OFFSCREEN_FORMAT,
+            camera,
+        ))
+    }
+
+    fn with_target(
+        adapter: &Adapter,
+        device: Device,
+        queue: Queue,
+        target: RenderTarget,
+        format: TextureFormat,
+        camera: &Camera,
+    ) -> Self {
+        let supported_sample_counts =
+            supported_sample_counts(adapter, &device, HDR_FORMAT);
+        let sample_count =
+            if supported_sample_counts.contains(&DEFAULT_SAMPLE_COUNT) {
+                DEFAULT_SAMPLE_COUNT
+            } else {
+                1
+            };
+        let (width, height) = target.size();
+        let depth_view =
+            create_depth_texture(&device, width, height, sample_count);
+        let msaa_view =
+            create_msaa_texture(&device, width, height, sample_count);
+        let hdr_view = create_hdr_texture(&device, width, height, 1);
+        let post = PostProcess::new(&device, format, width, height, &hdr_view);
 
         let (camera_bind_group, camera_buffer, camera_layout) =
             load_camera(&device, camera);
 
-        let (light_bind_group, _light_buffer, light_layout) =
-            load_light(&device, light);
-
         let texture_layout = texture_layout(&device);
-        let transform_layout = transform_layout(&device);
+
+        let joint_layout = joint_layout(&device);
+        let (joint_bind_group, joint_buffer) =
+            create_joint_buffer(&device, &joint_layout, INITIAL_JOINT_CAPACITY);
+
+        let shadow_map =
+            ShadowMap::new(&device, &joint_layout, &texture_layout);
+        let instance_buffer =
+            create_instance_buffer(&device, INITIAL_INSTANCE_CAPACITY);
+        let light_layout = light_layout(&device);
+        let (light_bind_group, light_buffer) = create_light_buffer(
+            &device,
+            &light_layout,
+            &shadow_map,
+            INITIAL_LIGHT_CAPACITY,
+        );
 
         let pipeline_layout =
             device.create_pipeline_layout(&PipelineLayoutDescriptor {
@@ -64,7 +300,7 @@ impl Gpu {
                     &camera_layout,
                     &light_layout,
                     &texture_layout,
-                    &transform_layout,
+                    &joint_layout,
                 ],
                 push_constant_ranges: &[],
             });
@@ -72,68 +308,288 @@ impl Gpu {
         let shader = device
             .create_shader_module(include_wgsl!("../../shaders/shader.wgsl"));
 
-        let render_pipeline =
-            device.create_render_pipeline(&RenderPipelineDescriptor {
-                label: None,
-                layout: Some(&pipeline_layout),
-                vertex: VertexState {
-                    module: &shader,
-                    entry_point: None,
-                    compilation_options: Default::default(),
-                    buffers: &[Vertex::layout()],
-                },
-                fragment: Some(FragmentState {
-                    module: &shader,
-                    entry_point: None,
-                    compilation_options: Default::default(),
-                    targets: &[Some(surface_config.format.into())],
-                }),
-                primitive: PrimitiveState {
-                    topology: PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: FrontFace::Ccw,
-                    cull_mode: Some(Face::Back),
-                    unclipped_depth: false,
-                    polygon_mode: PolygonMode::Fill,
-                    conservative: false,
-                },
-                depth_stencil: None,
-                multisample: MultisampleState::default(),
-                multiview: None,
-                cache: None,
-            });
-
-        log::info!("{:#?}", adapter.get_info());
+        let render_pipeline = create_render_pipeline(
+            &device,
+            &pipeline_layout,
+            &shader,
+            sample_count,
+            false,
+        );
+        let blend_pipeline = create_render_pipeline(
+            &device,
+            &pipeline_layout,
+            &shader,
+            sample_count,
+            true,
+        );
 
         Self {
-            surface,
-            surface_config,
+            target,
             device,
             queue,
             texture_layout,
-            transform_layout,
             render_pipeline,
+            blend_pipeline,
+            pipeline_layout,
+            shader,
+            shader_watch: None,
+            sample_count,
+            supported_sample_counts,
+            msaa_view,
+            hdr_view,
+            post,
             camera_bind_group,
             camera_buffer,
+            light_layout,
             light_bind_group,
-            _light_buffer,
-            meshes: Vec::new(),
+            light_buffer,
+            light_capacity: INITIAL_LIGHT_CAPACITY,
+            instance_buffer,
+            instance_capacity: INITIAL_INSTANCE_CAPACITY,
+            joint_layout,
+            joint_bind_group,
+            joint_buffer,
+            joint_capacity: INITIAL_JOINT_CAPACITY,
+            shadow_map,
+            depth_view,
+            clear_colour: DEFAULT_CLEAR_COLOUR,
+            cache: ResourceCache::default(),
+            frustum: camera.frustum(),
+            shadow_frustum: None,
+            camera_position: camera.position(),
+            cull_stats: CullStats::default(),
+            batching: true,
+            models: Vec::new(),
+            model_names: HashMap::new(),
         }
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
-        self.surface_config.height = height;
-        self.surface_config.width = width;
-        self.surface.configure(&self.device, &self.surface_config);
+        match &mut self.target {
+            RenderTarget::Surface { surface, config } => {
+                config.height = height;
+                config.width = width;
+                surface.configure(&self.device, config);
+            }
+            RenderTarget::Offscreen { texture } => {
+                *texture =
+                    create_offscreen_texture(&self.device, width, height);
+            }
+        }
+        self.hdr_view = create_hdr_texture(&self.device, width, height, 1);
+        self.post
+            .resize(&self.device, width, height, &self.hdr_view);
+        self.create_sample_targets(width, height);
+    }
+
+    /// Depth and MSAA targets, sized for the frame and multisampled to match
+    /// the pipeline
+    fn create_sample_targets(&mut self, width: u32, height: u32) {
+        self.depth_view = create_depth_texture(
+            &self.device,
+            width,
+            height,
+            self.sample_count,
+        );
+        self.msaa_view =
+            create_msaa_texture(&self.device, width, height, self.sample_count);
+    }
+
+    /// Dev mode, load the main shader from [`SHADER_PATH`] and rebuild the
+    /// pipelines whenever [`Gpu::reload_shaders`] finds it changed
+    pub fn watch_shaders(&mut self) {
+        if !Path::new(SHADER_PATH).exists() {
+            log::warn!("{SHADER_PATH} not found, shader edits will not reload");
+        }
+        self.shader_watch = Some(FileWatch::new(SHADER_PATH));
+        self.reload_shaders();
+    }
+
+    /// Rebuild the main pipelines if the watched shader changed on disk.
+    /// Errors are logged and the last pipelines that compiled stay in use
+    pub fn reload_shaders(&mut self) {
+        let Some(watch) = &mut self.shader_watch else {
+            return;
+        };
+        if !watch.changed() {
+            return;
+        }
+        let source = match fs::read_to_string(SHADER_PATH) {
+            Ok(source) => source,
+            Err(error) => {
+                log::error!("failed to read {SHADER_PATH}: {error}");
+                return;
+            }
+        };
+
+        // Catch compile errors instead of letting wgpu panic on them
+        self.device.push_error_scope(ErrorFilter::Validation);
+        let shader = self.device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(SHADER_PATH),
+            source: ShaderSource::Wgsl(source.into()),
+        });
+        let [render_pipeline, blend_pipeline] = [false, true].map(|blend| {
+            create_render_pipeline(
+                &self.device,
+                &self.pipeline_layout,
+                &shader,
+                self.sample_count,
+                blend,
+            )
+        });
+        if let Some(error) = pollster::block_on(self.device.pop_error_scope()) {
+            log::error!("keeping the last good pipelines, {error}");
+            return;
+        }
+        self.shader = shader;
+        self.render_pipeline = render_pipeline;
+        self.blend_pipeline = blend_pipeline;
+        log::info!("reloaded {SHADER_PATH}");
+    }
+
+    pub const fn exposure(&self) -> f32 {
+        self.post.exposure()
+    }
+
+    /// Scale every colour in the frame by `exposure` before tonemapping
+    pub fn set_exposure(&mut self, exposure: f32) {
+        let tonemapper = self.post.tonemapper();
+        self.post.set_tonemapping(&self.queue, exposure, tonemapper);
+    }
+
+    pub const fn tonemapper(&self) -> Tonemapper {
+        self.post.tonemapper()
+    }
+
+    pub fn set_tonemapper(&mut self, tonemapper: Tonemapper) {
+        let exposure = self.post.exposure();
+        self.post.set_tonemapping(&self.queue, exposure, tonemapper);
+    }
+
+    /// For making the GPU resources of [`PostStage`]s
+    pub const fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Run `stage` on every frame after the stages already added and before
+    /// tonemapping
+    pub fn push_post_stage(&mut self, stage: Box<dyn PostStage>) {
+        self.post.push(&self.device, stage, &self.hdr_view);
+    }
+
+    pub const fn set_batching(&mut self, batching: bool) {
+        self.batching = batching;
+    }
+
+    pub const fn sample_count(&self) -> u32 {
+        self.sample_count
     }
 
-    pub fn load_meshes(&mut self, models: impl Iterator<Item = assets::Mesh>) {
+    /// Sample counts [`Gpu::set_sample_count`] accepts, always includes 1
+    pub fn supported_sample_counts(&self) -> &[u32] {
+        &self.supported_sample_counts
+    }
+
+    /// Switch MSAA to `sample_count` samples per pixel, 1 turns it off. The
+    /// pipelines and render targets are rebuilt to match
+    pub fn set_sample_count(
+        &mut self,
+        sample_count: u32,
+    ) -> Result<(), UnsupportedSampleCount> {
+        if !self.supported_sample_counts.contains(&sample_count) {
+            return Err(UnsupportedSampleCount {
+                requested: sample_count,
+                supported: self.supported_sample_counts.clone(),
+            });
+        }
+        if sample_count == self.sample_count {
+            return Ok(());
+        }
+        self.sample_count = sample_count;
+        [self.render_pipeline, self.blend_pipeline] =
+            [false, true].map(|blend| {
+                create_render_pipeline(
+                    &self.device,
+                    &self.pipeline_layout,
+                    &self.shader,
+                    sample_count,
+                    blend,
+                )
+            });
+        let (width, height) = self.target.size();
+        self.create_sample_targets(width, height);
+        Ok(())
+    }
+
+    pub fn load_models(&mut self, models: impl Iterator<Item = assets::Model>) {
         models.for_each(|model| {
-            self.meshes.push(Rc::new(self.load_model(&model)))
+            self.load_model(model);
         })
     }
 
-    fn load_model(&self, model: &assets::Mesh) -> Mesh {
+    /// Upload every mesh in `model` and register it under its name. A model
+    /// loaded again under the same name replaces the old one in place, so
+    /// every [`MeshInstance`] of it draws the new meshes from the next frame
+    pub fn load_model(&mut self, model: assets::Model) -> ModelId {
+        let bounds = model
+            .meshes
+            .iter()
+            .map(|mesh| mesh.bounds)
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or(Aabb::from_points([]));
+        let blended_centre = model
+            .meshes
+            .iter()
+            .filter(|mesh| mesh.material.alpha_mode == AlphaMode::Blend)
+            .map(|mesh| mesh.bounds)
+            .reduce(|a, b| a.union(&b))
+            .map(|bounds| bounds.centre());
+        let meshes = model
+            .meshes
+            .iter()
+            .map(|mesh| self.load_mesh(mesh))
+            .collect();
+        let name = model.name;
+        let model = Model {
+            meshes,
+            bounds,
+            blended_centre,
+            skeleton: model.skeleton.map(Rc::new),
+            animation: model.animation.map(Rc::new),
+        };
+        if let Some(&model_id) = self.model_names.get(&name) {
+            log::info!("reloaded model {name}");
+            self.models[model_id.0] = model;
+            self.cache.evict_unused();
+            return model_id;
+        }
+        let model_id = ModelId(self.models.len());
+        self.models.push(model);
+        self.model_names.insert(name, model_id);
+        model_id
+    }
+
+    pub fn model(&self, name: &str) -> Result<ModelId, AssetError> {
+        self.model_names
+            .get(name)
+            .copied()
+            .ok_or_else(|| AssetError::UnknownModel(name.to_owned()))
+    }
+
+    /// Joints and clips of a skinned model, shared by every entity animating
+    /// it
+    pub fn skeleton(&self, model: ModelId) -> Option<Rc<Skeleton>> {
+        self.models[model.0].skeleton.clone()
+    }
+
+    /// Clips moving the nodes of a rigid model, for an [`AnimationPlayer`]
+    ///
+    /// [`AnimationPlayer`]: crate::animation::AnimationPlayer
+    pub fn animation(&self, model: ModelId) -> Option<Rc<NodeAnimation>> {
+        self.models[model.0].animation.clone()
+    }
+
+    fn load_mesh(&mut self, model: &assets::Mesh) -> Mesh {
         let index = self.device.create_buffer_init(&BufferInitDescriptor {
             label: None,
             usage: BufferUsages::INDEX,
@@ -145,9 +601,26 @@ impl Gpu {
             contents: bytemuck::cast_slice(&model.vertices),
         });
 
-        let sampler = self.device.create_sampler(&SamplerDescriptor::default());
+        let bind_group = self.load_material(&model.material);
+        Mesh {
+            vertex,
+            index,
+            indices_len: model.indices.len() as u32,
+            bind_group,
+            material: model.material.clone(),
+        }
+    }
+
+    /// The material's bind group, created the first time a mesh uses it
+    fn load_material(&mut self, material: &Rc<assets::Material>) -> BindGroup {
+        let key = Rc::as_ptr(material);
+        if let Some((_, bind_group)) = self.cache.materials.get(&key) {
+            return bind_group.clone();
+        }
 
-        let material_uniform = MaterialUniform::from(&model.material);
+        let samplers =
+            material.samplers.map(|sampler| self.load_sampler(sampler));
+        let material_uniform = MaterialUniform::from(&**material);
         let material_uniform_buffer =
             self.device.create_buffer_init(&BufferInitDescriptor {
                 label: None,
@@ -155,36 +628,67 @@ impl Gpu {
                 contents: bytes_of(&material_uniform),
             });
 
-        let texture_view = if let Some(ref image) = model.material.image {
-            let image = image.to_rgba8();
-            let size = Extent3d {
-                width: image.width(),
-                height: image.height(),
-                depth_or_array_layers: 1,
-            };
-            let texture = self.device.create_texture(&TextureDescriptor {
-                label: None,
-                size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba8UnormSrgb,
-                usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
-                view_formats: &[],
-            });
-            self.queue.write_texture(
-                texture.as_image_copy(),
-                &image,
-                TexelCopyBufferLayout {
-                    offset: 0,
-                    bytes_per_row: Some(image.width() * 4),
-                    rows_per_image: Some(image.height()),
+        let texture_views = material
+            .textures()
+            .into_iter()
+            .zip(MATERIAL_TEXTURE_FORMATS)
+            .map(|(image, format)| self.load_texture(image, format))
+            .collect::<Vec<_>>();
+
+        let mut entries = vec![BindGroupEntry {
+            binding: 0,
+            resource: material_uniform_buffer.as_entire_binding(),
+        }];
+        entries.extend(
+            MATERIAL_SAMPLER_BINDINGS.into_iter().zip(&samplers).map(
+                |(binding, sampler)| BindGroupEntry {
+                    binding,
+                    resource: BindingResource::Sampler(sampler),
                 },
-                size,
-            );
+            ),
+        );
+        entries.extend(
+            MATERIAL_TEXTURE_BINDINGS
+                .into_iter()
+                .zip(&texture_views)
+                .map(|(binding, view)| BindGroupEntry {
+                    binding,
+                    resource: BindingResource::TextureView(view),
+                }),
+        );
 
-            texture.create_view(&Default::default())
-        } else {
+        let bind_group = self.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Texture Bind Group"),
+            layout: &self.texture_layout,
+            entries: &entries,
+        });
+        self.cache
+            .materials
+            .insert(key, (material.clone(), bind_group.clone()));
+        bind_group
+    }
+
+    fn load_sampler(&mut self, sampler: assets::Sampler) -> Sampler {
+        self.cache
+            .samplers
+            .entry(sampler)
+            .or_insert_with(|| {
+                self.device.create_sampler(&sampler_descriptor(&sampler))
+            })
+            .clone()
+    }
+
+    /// Upload `image` unless it already has been, a missing image gets a 1x1
+    /// placeholder which the shader skips using the material's texture flags
+    fn load_texture(
+        &mut self,
+        image: Option<&Rc<DynamicImage>>,
+        format: TextureFormat,
+    ) -> TextureView {
+        let Some(image) = image else {
+            if let Some(view) = self.cache.placeholders.get(&format) {
+                return view.clone();
+            }
             let texture = self.device.create_texture(&TextureDescriptor {
                 label: None,
                 size: Extent3d {
@@ -195,91 +699,317 @@ impl Gpu {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba8UnormSrgb,
+                format,
                 usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
                 view_formats: &[],
             });
-            texture.create_view(&Default::default())
+            let view = texture.create_view(&Default::default());
+            self.cache.placeholders.insert(format, view.clone());
+            return view;
         };
+        let key = (Rc::as_ptr(image), format);
+        if let Some((_, view)) = self.cache.textures.get(&key) {
+            return view.clone();
+        }
 
-        let bind_group = self.device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Texture Bind Group"),
-            layout: &self.texture_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: material_uniform_buffer.as_entire_binding(),
+        let mips = mip_chain(image.to_rgba8());
+        let texture = self.device.create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: mips[0].width(),
+                height: mips[0].height(),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mips.len() as u32,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        for (level, mip) in mips.iter().enumerate() {
+            self.queue.write_texture(
+                TexelCopyTextureInfo {
+                    mip_level: level as u32,
+                    ..texture.as_image_copy()
                 },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::TextureView(&texture_view),
+                mip,
+                TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(mip.width() * 4),
+                    rows_per_image: Some(mip.height()),
                 },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: BindingResource::Sampler(&sampler),
+                Extent3d {
+                    width: mip.width(),
+                    height: mip.height(),
+                    depth_or_array_layers: 1,
                 },
-            ],
-        });
-        Mesh {
-            vertex,
-            index,
-            indices_len: model.indices.len() as u32,
-            bind_group,
+            );
         }
+
+        let view = texture.create_view(&Default::default());
+        self.cache
+            .textures
+            .insert(key, (image.clone(), view.clone()));
+        view
     }
 
-    pub fn get_mesh(&self, mesh: MeshId) -> MeshInstance {
-        let transform = self.device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Transform"),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-            contents: bytes_of(&Mat4::identity()),
+    pub fn get_mesh(&self, model: ModelId) -> MeshInstance {
+        MeshInstance { model }
+    }
+
+    pub fn set_clear_colour(&mut self, colour: Color) {
+        self.clear_colour = colour;
+    }
+
+    pub fn write_camera(&mut self, camera: &Camera) {
+        self.frustum = camera.frustum();
+        self.camera_position = camera.position();
+        self.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytes_of(&CameraUniform::from(camera)),
+        );
+    }
+
+    /// Upload `lights`, the shadow caster's shadow map is centred on
+    /// `shadow_focus`
+    pub fn write_lights(&mut self, lights: &Lights, shadow_focus: Vec3) {
+        let caster = lights.shadow_caster().map(|(index, light)| {
+            let view_projection =
+                light.shadow_view_projection(shadow_focus, SHADOW_RADIUS);
+            (index, view_projection)
         });
-        let bind_group = self.device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Transform"),
-            layout: &self.transform_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: transform.as_entire_binding(),
-            }],
+        self.shadow_frustum = caster.map(|(_, view_projection)| {
+            Frustum::from_view_perspective(&view_projection)
         });
+        let shadow = match caster {
+            Some((index, view_projection)) => ShadowUniform {
+                view_projection,
+                light: index as u32,
+                _padding: Default::default(),
+            },
+            None => ShadowUniform {
+                view_projection: Mat4::identity(),
+                light: ShadowUniform::NO_LIGHT,
+                _padding: Default::default(),
+            },
+        };
+        self.queue
+            .write_buffer(&self.shadow_map.buffer, 0, bytes_of(&shadow));
 
-        MeshInstance {
-            mesh: self.mesh_from_id(mesh),
-            transform,
+        let ambient = lights.ambient();
+        let lights: Vec<Light> = lights.iter().copied().collect();
+        if lights.len() > self.light_capacity {
+            self.light_capacity = lights.len().next_power_of_two();
+            (self.light_bind_group, self.light_buffer) = create_light_buffer(
+                &self.device,
+                &self.light_layout,
+                &self.shadow_map,
+                self.light_capacity,
+            );
+        }
 
-            bind_group,
+        let header = LightsHeader {
+            ambient,
+            count: lights.len() as u32,
+        };
+        self.queue
+            .write_buffer(&self.light_buffer, 0, bytes_of(&header));
+        if !lights.is_empty() {
+            self.queue.write_buffer(
+                &self.light_buffer,
+                size_of::<LightsHeader>() as u64,
+                bytemuck::cast_slice(&lights),
+            );
         }
     }
 
-    fn mesh_from_id(&self, id: MeshId) -> Rc<Mesh> {
-        match id {
-            MeshId::Ground => self.meshes[2].clone(),
-            MeshId::Cube => self.meshes[1].clone(),
-            MeshId::CubeGltf => self.meshes[0].clone(),
+    pub const fn cull_stats(&self) -> CullStats {
+        self.cull_stats
+    }
+
+    /// Batch the `entities` the camera sees and, separately, the ones the
+    /// shadow caster sees by model and upload all of their transforms and
+    /// joint matrices in one write each. Entities both passes draw have their
+    /// joint matrices uploaded once
+    fn write_instances(&mut self, entities: &[Entity]) -> DrawList {
+        let models = &self.models;
+        let visible = |frustum: Frustum| {
+            move |entity: &Entity| {
+                let bounds = &models[entity.mesh.model.0].bounds;
+                let bounds = match entity.joint_matrices() {
+                    Some(joints) => posed_bounds(bounds, joints),
+                    None => *bounds,
+                };
+                frustum.intersects(&bounds.transform(&entity.transform()))
+            }
+        };
+        let mut instances = Vec::with_capacity(entities.len());
+        let mut joints = JointMatrices::default();
+        let mut batches =
+            batch(entities, visible(self.frustum), &mut instances, &mut joints);
+        let drawn = instances.len();
+        let mut shadow_batches = match self.shadow_frustum {
+            Some(frustum) => {
+                batch(entities, visible(frustum), &mut instances, &mut joints)
+            }
+            None => Vec::new(),
+        };
+        if !self.batching {
+            batches = unbatch(batches);
+            shadow_batches = unbatch(shadow_batches);
+        }
+        let blended = back_to_front(
+            &batches,
+            &instances,
+            self.camera_position,
+            |model| self.models[model.0].blended_centre,
+        );
+        self.cull_stats = CullStats {
+            drawn,
+            culled: entities.len() - drawn,
+        };
+        if instances.len() > self.instance_capacity {
+            self.instance_capacity = instances.len().next_power_of_two();
+            self.instance_buffer =
+                create_instance_buffer(&self.device, self.instance_capacity);
+        }
+        if !instances.is_empty() {
+            self.queue.write_buffer(
+                &self.instance_buffer,
+                0,
+                bytemuck::cast_slice(&instances),
+            );
+        }
+        if joints.matrices.len() > self.joint_capacity {
+            self.joint_capacity = joints.matrices.len().next_power_of_two();
+            (self.joint_bind_group, self.joint_buffer) = create_joint_buffer(
+                &self.device,
+                &self.joint_layout,
+                self.joint_capacity,
+            );
+        }
+        if !joints.matrices.is_empty() {
+            self.queue.write_buffer(
+                &self.joint_buffer,
+                0,
+                bytemuck::cast_slice(&joints.matrices),
+            );
+        }
+        DrawList {
+            batches,
+            blended,
+            shadow_batches,
         }
     }
 
-    pub fn write_camera(&mut self, camera: &Mat4) {
-        self.queue
-            .write_buffer(&self.camera_buffer, 0, bytes_of(camera));
+    pub fn render(&mut self, entities: &[Entity]) -> SurfaceTexture {
+        let draws = self.write_instances(entities);
+        let RenderTarget::Surface { surface, .. } = &self.target else {
+            panic!("headless Gpu has no surface, use render_to_image");
+        };
+        let frame = surface.get_current_texture().unwrap();
+        let view = frame.texture.create_view(&Default::default());
+
+        let encoder = self.draw(&view, &draws);
+        self.queue.submit([encoder.finish()]);
+        frame
+    }
+
+    /// Draw a frame into the offscreen target and wait for the GPU to finish
+    /// it, without reading it back
+    pub fn render_offscreen(&mut self, entities: &[Entity]) {
+        let draws = self.write_instances(entities);
+        let RenderTarget::Offscreen { texture } = &self.target else {
+            panic!("windowed Gpu has no offscreen target, use render");
+        };
+        let view = texture.create_view(&Default::default());
+        let encoder = self.draw(&view, &draws);
+        self.queue.submit([encoder.finish()]);
+        self.device.poll(PollType::Wait).unwrap();
     }
 
-    pub fn render(&mut self, entities: &Vec<Entity>) -> SurfaceTexture {
-        let frame = self.surface.get_current_texture().unwrap();
-        let view = &frame.texture.create_view(&Default::default());
+    /// Draw a frame into the offscreen target and copy it back to the CPU
+    pub fn render_to_image(&mut self, entities: &[Entity]) -> RgbaImage {
+        let draws = self.write_instances(entities);
+        let RenderTarget::Offscreen { texture } = &self.target else {
+            panic!("windowed Gpu has no offscreen target, use render");
+        };
+        let view = texture.create_view(&Default::default());
+        let mut encoder = self.draw(&view, &draws);
+
+        let (width, height) = (texture.width(), texture.height());
+        // Rows in the copy buffer must be aligned, we strip the padding after
+        let unpadded_bytes_per_row = width * 4;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+        let readback = self.device.create_buffer(&BufferDescriptor {
+            label: Some("Readback"),
+            size: padded_bytes_per_row as u64 * height as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            TexelCopyBufferInfo {
+                buffer: &readback,
+                layout: TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit([encoder.finish()]);
+
+        let slice = readback.slice(..);
+        slice.map_async(MapMode::Read, |result| result.unwrap());
+        self.device.poll(PollType::Wait).unwrap();
+
+        let pixels = slice
+            .get_mapped_range()
+            .chunks(padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+            .copied()
+            .collect();
+        readback.unmap();
 
+        RgbaImage::from_raw(width, height, pixels).unwrap()
+    }
+
+    fn draw(&self, view: &TextureView, draws: &DrawList) -> CommandEncoder {
         let render_pass_desc = RenderPassDescriptor {
             label: None,
-            color_attachments: &[Some(RenderPassColorAttachment {
-                view,
-                resolve_target: None,
-                ops: Operations {
-                    load: LoadOp::Clear(Default::default()),
-                    // WARNING: This is important to vulkan but not dx12
-                    store: StoreOp::Store,
+            color_attachments: &[Some(match &self.msaa_view {
+                // Only the resolved frame is kept, the samples are dropped
+                Some(msaa_view) => RenderPassColorAttachment {
+                    view: msaa_view,
+                    resolve_target: Some(&self.hdr_view),
+                    ops: Operations {
+                        load: LoadOp::Clear(self.clear_colour),
+                        store: StoreOp::Discard,
+                    },
+                },
+                None => RenderPassColorAttachment {
+                    view: &self.hdr_view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(self.clear_colour),
+                        // WARNING: This is important to vulkan but not dx12
+                        store: StoreOp::Store,
+                    },
                 },
             })],
-            depth_stencil_attachment: None,
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
             timestamp_writes: None,
             occlusion_query_set: None,
         };
@@ -287,6 +1017,14 @@ impl Gpu {
         let mut encoder =
             self.device.create_command_encoder(&Default::default());
 
+        self.shadow_map.draw(
+            &mut encoder,
+            &self.models,
+            &draws.shadow_batches,
+            &self.instance_buffer,
+            &self.joint_bind_group,
+        );
+
         // GPU work goes here
         {
             let mut render_pass = encoder.begin_render_pass(&render_pass_desc);
@@ -294,86 +1032,509 @@ impl Gpu {
 
             render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
             render_pass.set_bind_group(1, &self.light_bind_group, &[]);
+            render_pass.set_bind_group(3, &self.joint_bind_group, &[]);
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
 
-            for entity in entities {
-                entity.mesh.write_transform(&self.queue, entity.transform());
-                render_pass.set_bind_group(
-                    2,
-                    &entity.mesh.mesh.bind_group,
-                    &[],
-                );
-                render_pass.set_bind_group(3, &entity.mesh.bind_group, &[]);
-
-                render_pass
-                    .set_vertex_buffer(0, entity.mesh.mesh.vertex.slice(..));
+            for batch in &draws.batches {
+                let meshes = &self.models[batch.model.0].meshes;
+                for mesh in meshes.iter().filter(|mesh| !mesh.blend()) {
+                    render_pass.set_bind_group(2, &mesh.bind_group, &[]);
+                    mesh.draw(&mut render_pass, batch.instances.clone());
+                }
+            }
 
-                render_pass.set_index_buffer(
-                    entity.mesh.mesh.index.slice(..),
-                    IndexFormat::Uint32,
-                );
-                render_pass.draw_indexed(
-                    0..entity.mesh.mesh.indices_len,
-                    0,
-                    0..1,
-                );
+            // One instance at a time so each blends over what is behind it
+            render_pass.set_pipeline(&self.blend_pipeline);
+            for draw in &draws.blended {
+                let meshes = &self.models[draw.model.0].meshes;
+                for mesh in meshes.iter().filter(|mesh| mesh.blend()) {
+                    render_pass.set_bind_group(2, &mesh.bind_group, &[]);
+                    mesh.draw(
+                        &mut render_pass,
+                        draw.instance..draw.instance + 1,
+                    );
+                }
             }
         }
-        self.queue.submit([encoder.finish()]);
-        frame
+        self.post.draw(&mut encoder, view);
+        encoder
     }
 }
 
-fn load_camera(
-    device: &Device,
-    camera: &Camera,
-) -> (BindGroup, Buffer, BindGroupLayout) {
-    let buffer = device.create_buffer_init(&BufferInitDescriptor {
-        label: None,
-        contents: bytes_of(&camera.view_perspective_rh()),
-        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-    });
+impl ShadowMap {
+    fn new(
+        device: &Device,
+        joint_layout: &BindGroupLayout,
+        texture_layout: &BindGroupLayout,
+    ) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Shadow Map"),
+            size: Extent3d {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&Default::default());
+        // Linear filtering with a comparison gives a 2x2 PCF tap for free
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Shadow Map"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            compare: Some(CompareFunction::LessEqual),
+            ..Default::default()
+        });
 
-    let min_binding_size = NonZeroU64::new(size_of::<Mat4>() as u64);
-    let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-        label: Some("Camera"),
-        entries: &[BindGroupLayoutEntry {
-            binding: 0,
-            visibility: ShaderStages::VERTEX,
-            count: None,
-            ty: BindingType::Buffer {
-                ty: BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size,
-            },
-        }],
-    });
-    let bind_group = device.create_bind_group(&BindGroupDescriptor {
-        label: Some("Camera"),
-        layout: &layout,
-        entries: &[BindGroupEntry {
-            binding: 0,
-            resource: buffer.as_entire_binding(),
-        }],
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Shadow"),
+            size: size_of::<ShadowUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Shadow"),
+                entries: &[shadow_uniform_layout_entry(
+                    0,
+                    ShaderStages::VERTEX,
+                )],
+            });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Shadow"),
+            layout: &layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        let shader = device
+            .create_shader_module(include_wgsl!("../../shaders/shadow.wgsl"));
+        let layouts = [&layout, joint_layout, texture_layout];
+        let create_pipeline = |masked: bool| {
+            let pipeline_layout =
+                device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                    label: Some("Shadow"),
+                    // Only the masked pipeline reads the material
+                    bind_group_layouts: &layouts[..2 + masked as usize],
+                    push_constant_ranges: &[],
+                });
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("Shadow"),
+                layout: Some(&pipeline_layout),
+                vertex: VertexState {
+                    module: &shader,
+                    entry_point: None,
+                    compilation_options: Default::default(),
+                    buffers: &[Vertex::layout(), instance_layout()],
+                },
+                fragment: masked.then(|| FragmentState {
+                    module: &shader,
+                    entry_point: None,
+                    compilation_options: Default::default(),
+                    targets: &[],
+                }),
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::TriangleList,
+                    front_face: FrontFace::Ccw,
+                    // Single sided meshes like the ground still cast shadows
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: CompareFunction::Less,
+                    stencil: StencilState::default(),
+                    // Push depths back a little so lit surfaces do not
+                    // shadow themselves
+                    bias: DepthBiasState {
+                        constant: 2,
+                        slope_scale: 2.0,
+                        clamp: 0.0,
+                    },
+                }),
+                multisample: MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        Self {
+            pipeline: create_pipeline(false),
+            masked_pipeline: create_pipeline(true),
+            view,
+            sampler,
+            buffer,
+            bind_group,
+        }
+    }
+
+    /// Render the depth of every batch from the light, `batches` are culled
+    /// against the light's view rather than the camera's. Blended meshes
+    /// cast no shadow
+    fn draw(
+        &self,
+        encoder: &mut CommandEncoder,
+        models: &[Model],
+        batches: &[Batch],
+        instances: &Buffer,
+        joints: &BindGroup,
+    ) {
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Shadow"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_bind_group(1, joints, &[]);
+        pass.set_vertex_buffer(1, instances.slice(..));
+
+        let meshes = |alpha_mode| {
+            batches.iter().flat_map(move |batch| {
+                models[batch.model.0]
+                    .meshes
+                    .iter()
+                    .filter(move |mesh| mesh.material.alpha_mode == alpha_mode)
+                    .map(|mesh| (mesh, batch.instances.clone()))
+            })
+        };
+        for (mesh, instances) in meshes(AlphaMode::Opaque) {
+            mesh.draw(&mut pass, instances);
+        }
+        pass.set_pipeline(&self.masked_pipeline);
+        for (mesh, instances) in meshes(AlphaMode::Mask) {
+            pass.set_bind_group(2, &mesh.bind_group, &[]);
+            mesh.draw(&mut pass, instances);
+        }
+    }
+}
+
+/// Entities sharing a model, drawn with one instanced draw per mesh
+#[derive(Debug, PartialEq)]
+struct Batch {
+    model: ModelId,
+    /// Where the batch's transforms are in the instance buffer
+    instances: Range<u32>,
+}
+
+/// Everything the main pass draws in a frame
+struct DrawList {
+    batches: Vec<Batch>,
+    /// Instances with blended meshes, furthest from the camera first
+    blended: Vec<BlendedDraw>,
+    /// What the shadow pass draws, in the same instance buffer
+    shadow_batches: Vec<Batch>,
+}
+
+/// One instance of a model with blended meshes, drawn on its own so it can
+/// be sorted against the others
+#[derive(Debug, PartialEq)]
+struct BlendedDraw {
+    model: ModelId,
+    /// Index of the transform in the instance buffer
+    instance: u32,
+}
+
+/// Every instance in `batches` whose model has blended meshes, sorted back
+/// to front by how far the centre of those meshes is from `eye`.
+/// `blended_centre` gives the centre in model space, [`None`] for models
+/// with nothing to blend
+fn back_to_front(
+    batches: &[Batch],
+    instances: &[InstanceTransform],
+    eye: Vec3,
+    blended_centre: impl Fn(ModelId) -> Option<Vec3>,
+) -> Vec<BlendedDraw> {
+    let mut draws: Vec<(f32, BlendedDraw)> = batches
+        .iter()
+        .filter_map(|batch| Some((batch, blended_centre(batch.model)?)))
+        .flat_map(|(batch, centre)| {
+            batch.instances.clone().map(move |instance| {
+                let transform = &instances[instance as usize].model;
+                let distance = (transform.transform_point(centre) - eye).len();
+                let draw = BlendedDraw {
+                    model: batch.model,
+                    instance,
+                };
+                (distance, draw)
+            })
+        })
+        .collect();
+    draws.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    draws.into_iter().map(|(_, draw)| draw).collect()
+}
+
+/// Joint matrices of the animated entities drawn in a frame, in the order
+/// they go into the joint buffer
+#[derive(Default)]
+struct JointMatrices {
+    matrices: Vec<Mat4>,
+    /// Where each entity's matrices start, by its index in the entities
+    offsets: HashMap<usize, u32>,
+}
+
+impl JointMatrices {
+    /// Where the matrices of entity `index` start, adding `matrices` the
+    /// first time the entity is drawn
+    fn offset(
+        &mut self,
+        index: usize,
+        matrices: impl IntoIterator<Item = Mat4>,
+    ) -> u32 {
+        *self.offsets.entry(index).or_insert_with(|| {
+            let offset = self.matrices.len() as u32;
+            self.matrices.extend(matrices);
+            offset
+        })
+    }
+}
+
+/// Group the `visible` entities by the model they share, in order of first
+/// appearance. Every instance is appended to `instances` batch by batch and
+/// the joint matrices of the animated ones to `joints`
+fn batch(
+    entities: &[Entity],
+    visible: impl Fn(&Entity) -> bool,
+    instances: &mut Vec<InstanceTransform>,
+    joints: &mut JointMatrices,
+) -> Vec<Batch> {
+    let mut groups: Vec<(ModelId, Vec<InstanceTransform>)> = Vec::new();
+    let mut group_of: HashMap<ModelId, usize> = HashMap::new();
+    for (index, entity) in entities.iter().enumerate() {
+        if !visible(entity) {
+            continue;
+        }
+        let model = entity.mesh.model;
+        let transform = entity.transform();
+        let group = *group_of.entry(model).or_insert_with(|| {
+            groups.push((model, Vec::new()));
+            groups.len() - 1
+        });
+        let mut instance = InstanceTransform::new(transform);
+        if let Some(matrices) = entity.joint_matrices() {
+            instance.joint_offset = joints.offset(index, matrices);
+        }
+        groups[group].1.push(instance);
+    }
+
+    groups
+        .into_iter()
+        .map(|(model, group)| {
+            let start = instances.len() as u32;
+            instances.extend(group);
+            Batch {
+                model,
+                instances: start..instances.len() as u32,
+            }
+        })
+        .collect()
+}
+
+/// Bounds of a skinned mesh in its pose. Every vertex is a weighted average
+/// of itself moved by its joints, so it stays within the bounds moved by each
+/// joint together with the unmoved bounds for unweighted vertices
+fn posed_bounds(bounds: &Aabb, joints: impl IntoIterator<Item = Mat4>) -> Aabb {
+    joints.into_iter().fold(*bounds, |posed, joint| {
+        posed.union(&bounds.transform(&joint))
+    })
+}
+
+/// Split `batches` into one batch per instance
+fn unbatch(batches: Vec<Batch>) -> Vec<Batch> {
+    batches
+        .into_iter()
+        .flat_map(|batch| {
+            batch.instances.map(move |instance| Batch {
+                model: batch.model,
+                instances: instance..instance + 1,
+            })
+        })
+        .collect()
+}
+
+fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: Some("Instances"),
+        size: (size_of::<InstanceTransform>() * capacity) as u64,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Per instance model and normal matrices, one column per attribute, and
+/// where the instance's joint matrices start
+fn instance_layout() -> VertexBufferLayout<'static> {
+    const ATTRIBUTES: [VertexAttribute; 8] = vertex_attr_array![
+        3 => Float32x4,
+        4 => Float32x4,
+        5 => Float32x4,
+        6 => Float32x4,
+        7 => Float32x4,
+        8 => Float32x4,
+        9 => Float32x4,
+        12 => Uint32
+    ];
+    VertexBufferLayout {
+        array_stride: size_of::<InstanceTransform>() as u64,
+        step_mode: VertexStepMode::Instance,
+        attributes: &ATTRIBUTES,
+    }
+}
+
+impl RenderTarget {
+    fn size(&self) -> (u32, u32) {
+        match self {
+            Self::Surface { config, .. } => (config.width, config.height),
+            Self::Offscreen { texture } => (texture.width(), texture.height()),
+        }
+    }
+}
+
+/// `image` followed by each half size version of it down to 1x1. The filter
+/// works on the raw values, which is slightly off for sRGB textures
+fn mip_chain(image: RgbaImage) -> Vec<RgbaImage> {
+    let mut mips = vec![image];
+    loop {
+        let last = mips.last().unwrap();
+        let (width, height) = last.dimensions();
+        if width == 1 && height == 1 {
+            return mips;
+        }
+        let (width, height) = ((width / 2).max(1), (height / 2).max(1));
+        mips.push(imageops::resize(last, width, height, FilterType::Triangle));
+    }
+}
+
+fn sampler_descriptor(sampler: &assets::Sampler) -> SamplerDescriptor<'_> {
+    let address_mode = |wrap| match wrap {
+        assets::Wrap::Repeat => AddressMode::Repeat,
+        assets::Wrap::MirroredRepeat => AddressMode::MirrorRepeat,
+        assets::Wrap::ClampToEdge => AddressMode::ClampToEdge,
+    };
+    let filter_mode = |filter| match filter {
+        assets::Filter::Nearest => FilterMode::Nearest,
+        assets::Filter::Linear => FilterMode::Linear,
+    };
+    let all_linear = [
+        Some(sampler.mag_filter),
+        Some(sampler.min_filter),
+        sampler.mipmap_filter,
+    ]
+    .iter()
+    .all(|filter| *filter == Some(assets::Filter::Linear));
+
+    SamplerDescriptor {
+        label: None,
+        address_mode_u: address_mode(sampler.wrap_u),
+        address_mode_v: address_mode(sampler.wrap_v),
+        mag_filter: filter_mode(sampler.mag_filter),
+        min_filter: filter_mode(sampler.min_filter),
+        mipmap_filter: sampler
+            .mipmap_filter
+            .map_or(FilterMode::Nearest, filter_mode),
+        // Without a mipmap filter only the full size level is used
+        lod_max_clamp: if sampler.mipmap_filter.is_some() {
+            32.0
+        } else {
+            0.0
+        },
+        // wgpu only allows anisotropy when every filter is linear
+        anisotropy_clamp: if all_linear { MAX_ANISOTROPY } else { 1 },
+        ..Default::default()
+    }
+}
+
+fn create_offscreen_texture(
+    device: &Device,
+    width: u32,
+    height: u32,
+) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some("Offscreen"),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: OFFSCREEN_FORMAT,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// Multisampled HDR target for the main pass to resolve from, [`None`] when
+/// `sample_count` is 1 and the pass draws straight into the HDR frame
+fn create_msaa_texture(
+    device: &Device,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> Option<TextureView> {
+    (sample_count > 1)
+        .then(|| create_hdr_texture(device, width, height, sample_count))
+}
+
+/// Has to match the sample count of the colour target it is drawn with
+fn create_depth_texture(
+    device: &Device,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> TextureView {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("Depth"),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
     });
-    (bind_group, buffer, layout)
+    texture.create_view(&Default::default())
 }
 
-fn load_light(
+fn load_camera(
     device: &Device,
-    light: &Light,
+    camera: &Camera,
 ) -> (BindGroup, Buffer, BindGroupLayout) {
     let buffer = device.create_buffer_init(&BufferInitDescriptor {
-        label: Some("Light"),
-        contents: bytes_of(light),
+        label: None,
+        contents: bytes_of(&CameraUniform::from(camera)),
         usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
     });
 
-    let min_binding_size = NonZeroU64::new(size_of::<Light>() as u64);
+    let min_binding_size = NonZeroU64::new(size_of::<CameraUniform>() as u64);
     let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-        label: Some("Light"),
+        label: Some("Camera"),
         entries: &[BindGroupLayoutEntry {
             binding: 0,
-            visibility: ShaderStages::FRAGMENT,
+            visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
             count: None,
             ty: BindingType::Buffer {
                 ty: BufferBindingType::Uniform,
@@ -383,135 +1544,445 @@ fn load_light(
         }],
     });
     let bind_group = device.create_bind_group(&BindGroupDescriptor {
-        label: Some("Light"),
+        label: Some("Camera"),
+        layout: &layout,
         entries: &[BindGroupEntry {
             binding: 0,
             resource: buffer.as_entire_binding(),
         }],
-        layout: &layout,
     });
-
     (bind_group, buffer, layout)
 }
 
-fn texture_layout(device: &Device) -> BindGroupLayout {
-    let min_binding_size = NonZeroU64::new(size_of::<MaterialUniform>() as u64);
-    let layout_descriptor = BindGroupLayoutDescriptor {
-        label: Some("Texture"),
+fn light_layout(device: &Device) -> BindGroupLayout {
+    // The header plus at least one light
+    let min_binding_size = NonZeroU64::new(
+        (size_of::<LightsHeader>() + size_of::<Light>()) as u64,
+    );
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Light"),
         entries: &[
             BindGroupLayoutEntry {
                 binding: 0,
                 visibility: ShaderStages::FRAGMENT,
+                count: None,
                 ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
+                    ty: BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
                     min_binding_size,
                 },
-                count: None,
             },
+            shadow_uniform_layout_entry(1, ShaderStages::FRAGMENT),
             BindGroupLayoutEntry {
-                binding: 1,
+                binding: 2,
                 visibility: ShaderStages::FRAGMENT,
+                count: None,
                 ty: BindingType::Texture {
-                    sample_type: TextureSampleType::Float { filterable: true },
+                    sample_type: TextureSampleType::Depth,
                     view_dimension: TextureViewDimension::D2,
                     multisampled: false,
                 },
-                count: None,
             },
             BindGroupLayoutEntry {
-                binding: 2,
+                binding: 3,
                 visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Sampler(SamplerBindingType::Filtering),
                 count: None,
+                ty: BindingType::Sampler(SamplerBindingType::Comparison),
             },
         ],
-    };
-    device.create_bind_group_layout(&layout_descriptor)
+    })
+}
+
+fn shadow_uniform_layout_entry(
+    binding: u32,
+    visibility: ShaderStages,
+) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility,
+        count: None,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: NonZeroU64::new(size_of::<ShadowUniform>() as u64),
+        },
+    }
 }
 
-fn transform_layout(device: &Device) -> BindGroupLayout {
-    let min_binding_size = NonZeroU64::new(size_of::<Mat4>() as u64);
-    let transform_layout_descriptor = BindGroupLayoutDescriptor {
-        label: Some("Transform"),
+/// Joint matrices read by the vertex stage of the main and shadow passes
+fn joint_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Joints"),
         entries: &[BindGroupLayoutEntry {
             binding: 0,
             visibility: ShaderStages::VERTEX,
+            count: None,
             ty: BindingType::Buffer {
-                ty: BufferBindingType::Uniform,
+                ty: BufferBindingType::Storage { read_only: true },
                 has_dynamic_offset: false,
-                min_binding_size,
+                min_binding_size: NonZeroU64::new(size_of::<Mat4>() as u64),
             },
-            count: None,
         }],
+    })
+}
+
+fn create_joint_buffer(
+    device: &Device,
+    layout: &BindGroupLayout,
+    capacity: usize,
+) -> (BindGroup, Buffer) {
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Joints"),
+        size: (size_of::<Mat4>() * capacity) as u64,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Joints"),
+        layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    });
+    (bind_group, buffer)
+}
+
+fn create_light_buffer(
+    device: &Device,
+    layout: &BindGroupLayout,
+    shadow_map: &ShadowMap,
+    capacity: usize,
+) -> (BindGroup, Buffer) {
+    let buffer = device.create_buffer(&BufferDescriptor {
+        label: Some("Light"),
+        size: (size_of::<LightsHeader>() + size_of::<Light>() * capacity)
+            as u64,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Light"),
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: shadow_map.buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(&shadow_map.view),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::Sampler(&shadow_map.sampler),
+            },
+        ],
+        layout,
+    });
+
+    (bind_group, buffer)
+}
+
+fn texture_layout(device: &Device) -> BindGroupLayout {
+    let min_binding_size = NonZeroU64::new(size_of::<MaterialUniform>() as u64);
+    let mut entries = vec![BindGroupLayoutEntry {
+        binding: 0,
+        visibility: ShaderStages::FRAGMENT,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size,
+        },
+        count: None,
+    }];
+    entries.extend(MATERIAL_SAMPLER_BINDINGS.map(|binding| {
+        BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        }
+    }));
+    entries.extend(MATERIAL_TEXTURE_BINDINGS.map(|binding| {
+        BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }
+    }));
+    let layout_descriptor = BindGroupLayoutDescriptor {
+        label: Some("Texture"),
+        entries: &entries,
     };
-    device.create_bind_group_layout(&transform_layout_descriptor)
+    device.create_bind_group_layout(&layout_descriptor)
+}
+
+/// The main pipeline, `blend` makes the variant for blended meshes which
+/// blends over the frame and leaves depth alone so they do not hide each
+/// other
+fn create_render_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    sample_count: u32,
+    blend: bool,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: None,
+            compilation_options: Default::default(),
+            buffers: &[Vertex::layout(), instance_layout()],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: None,
+            compilation_options: Default::default(),
+            targets: &[Some(ColorTargetState {
+                format: HDR_FORMAT,
+                blend: blend.then_some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: Some(Face::Back),
+            unclipped_depth: false,
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: !blend,
+            depth_compare: CompareFunction::Less,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Sample counts out of 1, 2, 4 and 8 that both `format` and the depth
+/// buffer can be rendered with
+fn supported_sample_counts(
+    adapter: &Adapter,
+    device: &Device,
+    format: TextureFormat,
+) -> Vec<u32> {
+    // Anything past what WebGPU guarantees needs the device feature
+    let adapter_specific = device
+        .features()
+        .contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    let flags = |format: TextureFormat| {
+        if adapter_specific {
+            adapter.get_texture_format_features(format).flags
+        } else {
+            format.guaranteed_format_features(device.features()).flags
+        }
+    };
+    let (colour, depth) = (flags(format), flags(DEPTH_FORMAT));
+    [1, 2, 4, 8]
+        .into_iter()
+        .filter(|&count| {
+            colour.sample_count_supported(count)
+                && depth.sample_count_supported(count)
+        })
+        .collect()
 }
 
 async fn init_wgpu(
     instance: &Instance,
-    surface: &Surface<'static>,
-) -> (Adapter, Device, Queue) {
+    surface: Option<&Surface<'static>>,
+    force_fallback_adapter: bool,
+) -> Option<(Adapter, Device, Queue)> {
     let adapter = instance
         .request_adapter(&RequestAdapterOptions {
             power_preference: Default::default(),
-            force_fallback_adapter: Default::default(),
-            compatible_surface: Some(surface),
+            force_fallback_adapter,
+            compatible_surface: surface,
         })
         .await
-        .unwrap();
+        .ok()?;
     let (device, queue) = adapter
         .request_device(&DeviceDescriptor {
             label: None,
-            required_features: Features::default(),
+            // Lets MSAA use every sample count the adapter supports
+            required_features: adapter.features()
+                & Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
             required_limits: Limits::default(),
             memory_hints: MemoryHints::Performance,
             trace: Trace::Off,
         })
         .await
-        .unwrap();
-    (adapter, device, queue)
+        .ok()?;
+    Some((adapter, device, queue))
 }
 
-pub struct MeshInstance {
-    mesh: Rc<Mesh>,
-    transform: Buffer,
-    bind_group: BindGroup,
+/// Returned by [`Gpu::set_sample_count`] for a sample count the adapter
+/// cannot render with
+#[derive(Debug)]
+pub struct UnsupportedSampleCount {
+    pub requested: u32,
+    pub supported: Vec<u32>,
 }
-impl MeshInstance {
-    fn write_transform(&self, queue: &Queue, matrix: Mat4) {
-        queue.write_buffer(&self.transform, 0, bytes_of(&matrix));
+
+impl fmt::Display for UnsupportedSampleCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x MSAA is not supported, expected one of {:?}",
+            self.requested, self.supported
+        )
     }
 }
 
+impl std::error::Error for UnsupportedSampleCount {}
+
+/// Handle to a model registered with [`Gpu::load_model`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ModelId(usize);
+
+/// A model placed in the world by an entity, entities sharing a model are
+/// drawn together with instancing. Only a handle, the transform comes from
+/// the entity each frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshInstance {
+    model: ModelId,
+}
+
+pub struct Model {
+    meshes: Vec<Mesh>,
+    /// Bounds of every mesh together
+    bounds: Aabb,
+    /// Middle of the blended meshes, what instances are sorted by, [`None`]
+    /// when every mesh is opaque or masked
+    blended_centre: Option<Vec3>,
+    skeleton: Option<Rc<Skeleton>>,
+    animation: Option<Rc<NodeAnimation>>,
+}
+
 pub struct Mesh {
     vertex: Buffer,
     index: Buffer,
     indices_len: u32,
     bind_group: BindGroup,
+    /// Kept so [`ResourceCache`] can tell which materials are still drawn
+    material: Rc<assets::Material>,
+}
+
+impl Mesh {
+    /// Uses [`AlphaMode::Blend`] and is drawn after everything opaque
+    fn blend(&self) -> bool {
+        self.material.alpha_mode == AlphaMode::Blend
+    }
+
+    fn draw(&self, pass: &mut RenderPass, instances: Range<u32>) {
+        pass.set_vertex_buffer(0, self.vertex.slice(..));
+        pass.set_index_buffer(self.index.slice(..), IndexFormat::Uint32);
+        pass.draw_indexed(0..self.indices_len, 0, instances);
+    }
+}
+
+/// One entity's transforms in the instance buffer
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+#[repr(C)]
+struct InstanceTransform {
+    model: Mat4,
+    /// Columns of the upper 3x3 of the normal matrix, w is unused
+    normal: [Vec4; 3],
+    /// Index of the first joint matrix in the joint buffer
+    joint_offset: u32,
+}
+impl InstanceTransform {
+    /// The entity is not skinned
+    const NO_JOINTS: u32 = u32::MAX;
+
+    fn new(model: Mat4) -> Self {
+        let normal = model.normal_matrix();
+        Self {
+            model,
+            normal: [normal.x, normal.y, normal.z],
+            joint_offset: Self::NO_JOINTS,
+        }
+    }
+}
+
+/// Sits in front of the array of lights in the light storage buffer
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+#[repr(C)]
+struct LightsHeader {
+    /// Ambient colour already scaled by its strength
+    ambient: Vec3,
+    count: u32,
+}
+
+/// Where the shadow map is rendered from and which light it belongs to
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+#[repr(C)]
+struct ShadowUniform {
+    view_projection: Mat4,
+    /// Index of the shadow casting light in the light storage buffer
+    light: u32,
+    _padding: [u32; 3],
+}
+impl ShadowUniform {
+    /// No light casts shadows
+    const NO_LIGHT: u32 = u32::MAX;
 }
 
 #[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
 #[repr(C)]
 pub struct MaterialUniform {
     base_colour: [f32; 4],
+    emissive: [f32; 3],
     metallic: f32,
     roughness: f32,
-    has_texture: u32,
-    _padding: [u8; 4],
+    normal_scale: f32,
+    occlusion_strength: f32,
+    /// One bit per texture the material has, in binding order
+    textures: u32,
+    /// [`AlphaMode`] in declaration order
+    alpha_mode: u32,
+    alpha_cutoff: f32,
+    _padding: [u8; 8],
 }
-impl MaterialUniform {
-    pub fn new(
-        base_colour: [f32; 4],
-        metallic: f32,
-        roughness: f32,
-        has_texture: bool,
-    ) -> Self {
+impl From<&assets::Material> for MaterialUniform {
+    fn from(material: &assets::Material) -> Self {
+        let textures = material
+            .textures()
+            .iter()
+            .enumerate()
+            .fold(0, |flags, (bit, texture)| {
+                flags | ((texture.is_some() as u32) << bit)
+            });
         Self {
-            base_colour,
-            metallic,
-            roughness,
-            has_texture: has_texture as u32,
+            base_colour: material.base_colour,
+            emissive: material.emissive,
+            metallic: material.metallic,
+            roughness: material.roughness,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            textures,
+            alpha_mode: material.alpha_mode as u32,
+            alpha_cutoff: material.alpha_cutoff,
             _padding: Default::default(),
         }
     }
@@ -520,16 +1991,39 @@ impl MaterialUniform {
 #[derive(bytemuck::Pod, bytemuck::Zeroable, Copy, Clone, Debug)]
 #[repr(C)]
 pub struct Vertex {
-    vec3: Vec3,
-    normal: Vec3,
-    uv: [f32; 2],
+    pub vec3: Vec3,
+    pub normal: Vec3,
+    pub uv: Vec2,
+    /// Joints of the model's skeleton that move this vertex
+    pub joints: [u32; 4],
+    /// How much each of `joints` counts, all zero when not skinned
+    pub weights: [f32; 4],
 }
 impl Vertex {
-    const ATTRIBUTES: [VertexAttribute; 3] =
-        vertex_attr_array![0 => Float32x3, 1 => Float32x3 ,2 => Float32x2];
+    const ATTRIBUTES: [VertexAttribute; 5] = vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x3,
+        2 => Float32x2,
+        10 => Uint32x4,
+        11 => Float32x4
+    ];
 
-    pub fn new(vec3: Vec3, normal: Vec3, uv: [f32; 2]) -> Self {
-        Self { vec3, normal, uv }
+    pub fn new(vec3: Vec3, normal: Vec3, uv: Vec2) -> Self {
+        Self {
+            vec3,
+            normal,
+            uv,
+            joints: [0; 4],
+            weights: [0.0; 4],
+        }
+    }
+
+    pub const fn skinned(self, joints: [u32; 4], weights: [f32; 4]) -> Self {
+        Self {
+            joints,
+            weights,
+            ..self
+        }
     }
 
     const fn layout() -> VertexBufferLayout<'static> {
@@ -540,3 +2034,561 @@ impl Vertex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use winit::dpi::PhysicalSize;
+
+    use super::*;
+    use crate::{
+        animation::{AnimationPlayer, Joint, Transform},
+        graphics::{AssetCache, Vignette, load_assets, scene_light},
+        maths::Quat,
+    };
+
+    /// Bright enough that tonemapping saturates it to pure red
+    const HDR_RED: Color = Color {
+        r: 100.0,
+        ..Color::BLACK
+    };
+
+    /// A headless [`Gpu`] and the camera it renders from. Tests using it
+    /// are ignored by default, `cargo test -- --ignored` runs them and fails
+    /// when not even the fallback adapter can be created
+    fn headless_gpu(size: PhysicalSize<u32>) -> (Gpu, Camera) {
+        let camera = Camera::new(&size);
+        let gpu = Gpu::headless(size.width, size.height, &camera)
+            .expect("no wgpu adapter, not even the fallback one");
+        (gpu, camera)
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn headless_render_to_image() {
+        let size = PhysicalSize::new(64, 64);
+        let (mut gpu, _) = headless_gpu(size);
+        gpu.load_models(load_assets(&mut AssetCache::default()));
+        let mut lights = Lights::new();
+        lights.add(scene_light());
+        gpu.write_lights(&lights, Vec3::zeroes());
+        gpu.set_clear_colour(HDR_RED);
+
+        let cube = Entity::new(
+            Vec3::zeroes(),
+            Vec3::xyz(0.1),
+            gpu.get_mesh(gpu.model("cube").unwrap()),
+            false,
+        );
+        let frame = gpu.render_to_image(&[cube]);
+
+        assert_eq!(frame.dimensions(), (size.width, size.height));
+        assert_eq!(frame.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_ne!(frame.get_pixel(32, 32).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn reloading_a_model_evicts_its_old_material() {
+        let (mut gpu, _) = headless_gpu(PhysicalSize::new(64, 64));
+        let load = || {
+            assets::Model::load("assets/cube.glb", &mut AssetCache::default())
+                .unwrap()
+        };
+        gpu.load_model(load());
+        let cached = gpu.cache.materials.len();
+
+        // Each load makes new materials, as exporting again would
+        gpu.load_model(load());
+        assert_eq!(gpu.cache.materials.len(), cached);
+        assert!(
+            gpu.cache
+                .textures
+                .values()
+                .all(|(image, _)| { Rc::strong_count(image) > 1 })
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn non_uniform_scale_keeps_normals_lit() {
+        let size = PhysicalSize::new(64, 64);
+        let (mut gpu, camera) = headless_gpu(size);
+        let mut lights = Lights::new();
+        lights.add(scene_light());
+        gpu.write_lights(&lights, Vec3::zeroes());
+
+        // The same stretch once as the entity's scale and once baked into
+        // the mesh, with the normals transformed by the inverse transpose
+        let scale = Vec3::new(0.3, 0.1, 0.15);
+        let cube =
+            assets::Model::load("assets/cube.glb", &mut AssetCache::default())
+                .unwrap();
+        let mut stretched = assets::Model::load_as(
+            "stretched",
+            "assets/cube.glb",
+            &mut AssetCache::default(),
+        )
+        .unwrap();
+        let inverse = Vec3::new(1.0 / scale.x, 1.0 / scale.y, 1.0 / scale.z);
+        stretched.meshes = stretched
+            .meshes
+            .into_iter()
+            .map(|mesh| {
+                let vertices = mesh
+                    .vertices
+                    .into_iter()
+                    .map(|vertex| Vertex {
+                        vec3: vertex.vec3 * scale,
+                        normal: (vertex.normal * inverse).normalise(),
+                        ..vertex
+                    })
+                    .collect();
+                assets::Mesh::new(vertices, mesh.indices, mesh.material)
+            })
+            .collect();
+        let centre = stretched.meshes[0].bounds.centre();
+        gpu.load_models([cube, stretched].into_iter());
+
+        let mut render = |name, scale| {
+            let entity = Entity::new(
+                Vec3::zeroes(),
+                scale,
+                gpu.get_mesh(gpu.model(name).unwrap()),
+                false,
+            );
+            gpu.render_to_image(&[entity])
+        };
+        let scaled = render("cube", scale);
+        let baked = render("stretched", Vec3::xyz(1.0));
+
+        // Compare the surface in front of the middle of the cube
+        let centre = camera.view_perspective_rh().transform_point(centre);
+        let x = ((centre.x + 1.0) / 2.0 * size.width as f32) as u32;
+        let y = ((1.0 - centre.y) / 2.0 * size.height as f32) as u32;
+        let (scaled, baked) =
+            (scaled.get_pixel(x, y).0, baked.get_pixel(x, y).0);
+        assert_ne!(baked, [0, 0, 0, 255]);
+        for (scaled, baked) in scaled.into_iter().zip(baked) {
+            assert!(scaled.abs_diff(baked) <= 2, "{scaled} {baked}");
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn sample_count_changes_at_runtime() {
+        let (mut gpu, _) = headless_gpu(PhysicalSize::new(64, 64));
+        gpu.set_clear_colour(HDR_RED);
+
+        assert!(gpu.set_sample_count(3).is_err());
+        for sample_count in gpu.supported_sample_counts().to_vec() {
+            gpu.set_sample_count(sample_count).unwrap();
+            assert_eq!(gpu.sample_count(), sample_count);
+            let frame = gpu.render_to_image(&[]);
+            assert_eq!(frame.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn reloaded_models_keep_their_id() {
+        let (mut gpu, _) = headless_gpu(PhysicalSize::new(16, 16));
+        let cube = gpu.load_model(
+            assets::Model::load("assets/cube.glb", &mut AssetCache::default())
+                .unwrap(),
+        );
+        let ground = assets::Model::load_as(
+            "cube",
+            "assets/ground.glb",
+            &mut AssetCache::default(),
+        )
+        .unwrap();
+        let bounds = ground
+            .meshes
+            .iter()
+            .map(|mesh| mesh.bounds)
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+
+        assert_eq!(gpu.load_model(ground), cube);
+        assert_eq!(gpu.models.len(), 1);
+        assert_eq!(gpu.models[cube.0].bounds.max, bounds.max);
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn exposure_scales_the_frame() {
+        let (mut gpu, _) = headless_gpu(PhysicalSize::new(16, 16));
+        gpu.set_clear_colour(Color::WHITE);
+        let bright = gpu.render_to_image(&[]).get_pixel(0, 0).0;
+        gpu.set_exposure(0.0);
+        assert_eq!(gpu.render_to_image(&[]).get_pixel(0, 0).0, [0, 0, 0, 255]);
+
+        // Reinhard maps 1 to 0.5 where ACES is brighter
+        gpu.set_exposure(1.0);
+        gpu.set_tonemapper(Tonemapper::Reinhard);
+        let reinhard = gpu.render_to_image(&[]).get_pixel(0, 0).0;
+        assert!(reinhard[0] < bright[0] && reinhard[0] > 0);
+    }
+
+    /// Ignores the frame and fills the output with one colour
+    struct Fill(Color);
+
+    impl PostStage for Fill {
+        fn bind(&mut self, _: &Device, _: &TextureView) {}
+
+        fn draw(&self, encoder: &mut CommandEncoder, output: &TextureView) {
+            encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Fill"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(self.0),
+                        store: StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn post_stages_run_before_tonemapping() {
+        let (mut gpu, _) = headless_gpu(PhysicalSize::new(16, 16));
+        gpu.set_clear_colour(Color::WHITE);
+
+        // Tonemapping saturates the HDR colour the stage draws
+        gpu.push_post_stage(Box::new(Fill(HDR_RED)));
+        assert_eq!(
+            gpu.render_to_image(&[]).get_pixel(0, 0).0,
+            [255, 0, 0, 255]
+        );
+
+        // Each stage draws over what the one before it drew
+        let green = Color {
+            g: 100.0,
+            ..Color::BLACK
+        };
+        gpu.push_post_stage(Box::new(Fill(green)));
+        assert_eq!(
+            gpu.render_to_image(&[]).get_pixel(0, 0).0,
+            [0, 255, 0, 255]
+        );
+
+        // Still chained after the frames are remade
+        gpu.resize(8, 8);
+        assert_eq!(
+            gpu.render_to_image(&[]).get_pixel(0, 0).0,
+            [0, 255, 0, 255]
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter"]
+    fn vignette_darkens_the_corners() {
+        let (mut gpu, _) = headless_gpu(PhysicalSize::new(16, 16));
+        gpu.set_clear_colour(Color {
+            r: 0.5,
+            g: 0.5,
+            b: 0.5,
+            a: 1.0,
+        });
+        gpu.push_post_stage(Box::new(Vignette::new(gpu.device())));
+
+        let frame = gpu.render_to_image(&[]);
+        assert!(frame.get_pixel(0, 0).0[0] < frame.get_pixel(8, 8).0[0]);
+    }
+
+    #[test]
+    fn normal_matrix_lights_stretched_surfaces() {
+        // A face sloping at 45 degrees, stretching along x makes it
+        // shallower. Nothing is translated so transform_point works on
+        // directions
+        let model = Mat4::from_scaling(Vec3::new(4.0, 1.0, 1.0));
+        let slope = Vec3::new(1.0, 1.0, 0.0).normalise();
+        let edge = model.transform_point(Vec3::new(1.0, -1.0, 0.0));
+
+        let [x, y, z] = InstanceTransform::new(model).normal;
+        let normal_matrix = Mat4 {
+            x,
+            y,
+            z,
+            w: Vec4::new(0.0, 0.0, 0.0, 1.0),
+        };
+        let normal = normal_matrix.transform_point(slope).normalise();
+        assert!(normal.dot(&edge).abs() < 1e-5);
+
+        // Light from straight above now hits the face nearly head on
+        let lit = normal.dot(&Vec3::y());
+        assert!((lit - 4.0 / 17.0f32.sqrt()).abs() < 1e-5);
+
+        // Transforming the normal like a position gets it badly wrong
+        let skewed = model.transform_point(slope).normalise();
+        assert!((skewed.dot(&Vec3::y()) - lit).abs() > 0.5);
+    }
+
+    #[test]
+    fn mip_chain_halves_down_to_one_pixel() {
+        let image = RgbaImage::from_pixel(8, 2, image::Rgba([10, 20, 30, 255]));
+        let mips = mip_chain(image);
+
+        let sizes: Vec<_> = mips.iter().map(RgbaImage::dimensions).collect();
+        assert_eq!(sizes, [(8, 2), (4, 1), (2, 1), (1, 1)]);
+        assert_eq!(mips[3].get_pixel(0, 0).0, [10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn entities_are_batched_by_model() {
+        let entity = |model, x| {
+            Entity::new(
+                Vec3::new(x, 0.0, 0.0),
+                Vec3::xyz(1.0),
+                MeshInstance {
+                    model: ModelId(model),
+                },
+                false,
+            )
+        };
+        let entities = [
+            entity(1, 0.0),
+            entity(0, 1.0),
+            entity(1, 2.0),
+            entity(1, 3.0),
+        ];
+
+        let (mut instances, mut joints) =
+            (Vec::new(), JointMatrices::default());
+        let batches = batch(&entities, |_| true, &mut instances, &mut joints);
+        assert_eq!(
+            batches,
+            [
+                Batch {
+                    model: ModelId(1),
+                    instances: 0..3
+                },
+                Batch {
+                    model: ModelId(0),
+                    instances: 3..4
+                },
+            ]
+        );
+        let xs: Vec<f32> = instances
+            .iter()
+            .map(|instance| instance.model.w.x)
+            .collect();
+        assert_eq!(xs, [0.0, 2.0, 3.0, 1.0]);
+        assert!(joints.matrices.is_empty());
+
+        let mut instances = Vec::new();
+        let batches = batch(
+            &entities,
+            |entity| entity.mesh.model == ModelId(0),
+            &mut instances,
+            &mut joints,
+        );
+        assert_eq!(
+            batches,
+            [Batch {
+                model: ModelId(0),
+                instances: 0..1
+            }]
+        );
+        assert_eq!(instances.len(), 1);
+    }
+
+    #[test]
+    fn entities_scale_then_rotate_then_translate() {
+        let mut entity = Entity::new(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::xyz(2.0),
+            MeshInstance { model: ModelId(0) },
+            false,
+        );
+        // Moving the entity does not scale how far it moves
+        assert_eq!(
+            entity.transform().transform_point(Vec3::zeroes()),
+            entity.position()
+        );
+
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        entity.rotate(Quat::from_axis_angle(z, std::f32::consts::FRAC_PI_2));
+        let point = entity.transform().transform_point(Vec3::x());
+        assert!((point - Vec3::new(0.0, 2.0, 5.0)).len() < 1e-5, "{point:?}");
+    }
+
+    #[test]
+    fn attached_lights_turn_with_their_entity() {
+        let mut lights = Lights::new();
+        let torch =
+            lights.add(Light::point(Vec3::zeroes(), Vec3::xyz(1.0), 1.0));
+        let mut entity = Entity::new(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::xyz(1.0),
+            MeshInstance { model: ModelId(0) },
+            false,
+        );
+        entity.attach_light(torch, Vec3::x());
+        entity.set_rotation(Quat::from_axis_angle(
+            Vec3::y(),
+            std::f32::consts::FRAC_PI_2,
+        ));
+
+        lights.follow(&[entity]);
+        let position = lights.get_mut(torch).unwrap().position();
+        assert!(
+            (position - Vec3::new(0.0, 0.0, 4.0)).len() < 1e-5,
+            "{position:?}"
+        );
+    }
+
+    #[test]
+    fn skinned_entities_point_at_their_joints() {
+        let joint = Joint {
+            parent: None,
+            parent_transform: Mat4::identity(),
+            rest: Transform::default(),
+            inverse_bind: Mat4::identity(),
+        };
+        let skeleton = Rc::new(Skeleton {
+            joints: vec![joint; 3],
+            clips: Vec::new(),
+        });
+        let entity = |skinned| {
+            let mut entity = Entity::new(
+                Vec3::zeroes(),
+                Vec3::xyz(1.0),
+                MeshInstance { model: ModelId(0) },
+                false,
+            );
+            if skinned {
+                entity.set_skeleton(skeleton.clone());
+            }
+            entity
+        };
+        let entities = [entity(true), entity(false), entity(true)];
+
+        let (mut instances, mut joints) =
+            (Vec::new(), JointMatrices::default());
+        batch(&entities, |_| true, &mut instances, &mut joints);
+        let offsets: Vec<u32> = instances
+            .iter()
+            .map(|instance| instance.joint_offset)
+            .collect();
+        assert_eq!(offsets, [0, InstanceTransform::NO_JOINTS, 3]);
+        assert_eq!(joints.matrices, [Mat4::identity(); 6]);
+    }
+
+    #[test]
+    fn both_passes_share_the_joint_matrices() {
+        let skeleton = Rc::new(Skeleton {
+            joints: vec![Joint {
+                parent: None,
+                parent_transform: Mat4::identity(),
+                rest: Transform::default(),
+                inverse_bind: Mat4::identity(),
+            }],
+            clips: Vec::new(),
+        });
+        let entities: Vec<Entity> = (0..2)
+            .map(|_| {
+                let mut entity = Entity::new(
+                    Vec3::zeroes(),
+                    Vec3::xyz(1.0),
+                    MeshInstance { model: ModelId(0) },
+                    false,
+                );
+                entity.set_skeleton(skeleton.clone());
+                entity
+            })
+            .collect();
+
+        let mut joints = JointMatrices::default();
+        let (mut camera, mut shadow) = (Vec::new(), Vec::new());
+        batch(&entities, |_| true, &mut camera, &mut joints);
+        // The shadow pass only sees the second entity
+        let second = |entity: &Entity| std::ptr::eq(entity, &entities[1]);
+        batch(&entities, second, &mut shadow, &mut joints);
+
+        assert_eq!(shadow[0].joint_offset, camera[1].joint_offset);
+        assert_eq!(joints.matrices.len(), 2);
+    }
+
+    #[test]
+    fn animated_nodes_follow_the_skin() {
+        let lowered = Mat4::from_translation(Vec3::new(0.0, -1.0, 0.0));
+        let node = Joint {
+            parent: None,
+            parent_transform: Mat4::identity(),
+            rest: Transform::default(),
+            inverse_bind: lowered,
+        };
+        let animation = NodeAnimation {
+            nodes: Skeleton {
+                joints: vec![node],
+                clips: Vec::new(),
+            },
+            first_joint: 2,
+        };
+        let mut entity = Entity::new(
+            Vec3::zeroes(),
+            Vec3::xyz(1.0),
+            MeshInstance { model: ModelId(0) },
+            false,
+        );
+        entity.player = Some(AnimationPlayer::new(Rc::new(animation)));
+
+        // The skin stays in bind pose without an animator
+        let (mut instances, mut joints) =
+            (Vec::new(), JointMatrices::default());
+        batch(&[entity], |_| true, &mut instances, &mut joints);
+        assert_eq!(instances[0].joint_offset, 0);
+        assert_eq!(
+            joints.matrices,
+            [Mat4::identity(), Mat4::identity(), lowered]
+        );
+    }
+
+    #[test]
+    fn posed_bounds_cover_every_joint() {
+        let bounds = Aabb::from_points([Vec3::zeroes(), Vec3::xyz(1.0)]);
+        let raised = Mat4::from_translation(Vec3::new(0.0, 5.0, 0.0));
+        let posed = posed_bounds(&bounds, [Mat4::identity(), raised]);
+        assert_eq!(posed.min, Vec3::zeroes());
+        assert_eq!(posed.max, Vec3::new(1.0, 6.0, 1.0));
+        assert_eq!(posed_bounds(&bounds, []), bounds);
+    }
+
+    #[test]
+    fn blended_instances_are_drawn_back_to_front() {
+        let instance = |x| {
+            InstanceTransform::new(Mat4::from_translation(Vec3::new(
+                x, 0.0, 0.0,
+            )))
+        };
+        let instances = [instance(1.0), instance(5.0), instance(3.0)];
+        let batches = [
+            Batch {
+                model: ModelId(0),
+                instances: 0..2,
+            },
+            Batch {
+                model: ModelId(1),
+                instances: 2..3,
+            },
+        ];
+        let draw = |model, instance| BlendedDraw {
+            model: ModelId(model),
+            instance,
+        };
+
+        // Model 0 blends a mesh sitting 1 along x from its origin
+        let centre = |model| (model == ModelId(0)).then(Vec3::x);
+        let draws = back_to_front(&batches, &instances, Vec3::zeroes(), centre);
+        assert_eq!(draws, [draw(0, 1), draw(0, 0)]);
+
+        let centre = |_| Some(Vec3::zeroes());
+        let draws = back_to_front(&batches, &instances, Vec3::zeroes(), centre);
+        assert_eq!(draws, [draw(0, 1), draw(1, 2), draw(0, 0)]);
+    }
+}
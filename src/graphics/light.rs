@@ -1,23 +1,276 @@
 use bytemuck::{Pod, Zeroable};
 
-use crate::maths::Vec3;
+use crate::{
+    game::Entity,
+    maths::{Mat4, Vec3},
+};
 
-#[derive(Zeroable, Pod, Copy, Clone)]
+const POINT: u32 = 0;
+const DIRECTIONAL: u32 = 1;
+const SPOT: u32 = 2;
+
+/// A light as the shader sees it, one entry in the light storage buffer
+#[derive(Zeroable, Pod, Copy, Clone, Debug)]
 #[repr(C)]
 pub struct Light {
     position: Vec3,
-    _padding: [u8; 4],
-    color: Vec3,
+    kind: u32,
+    /// Which way the light shines, unused by point lights
+    direction: Vec3,
     intensity: f32,
+    color: Vec3,
+    /// Cosine of the spot cone angle inside which the light is at full
+    /// intensity
+    inner_cone: f32,
+    /// Cosine of the spot cone angle outside which there is no light
+    outer_cone: f32,
+    /// How far point and spot lights reach, 0 is unlimited
+    range: f32,
+    _padding: [u8; 8],
 }
 
 impl Light {
-    pub fn new(position: Vec3, color: Vec3, intensity: f32) -> Self {
+    pub fn point(position: Vec3, color: Vec3, intensity: f32) -> Self {
         Light {
             position,
-            color,
+            kind: POINT,
+            direction: Vec3::zeroes(),
             intensity,
+            color,
+            inner_cone: 0.0,
+            outer_cone: 0.0,
+            range: 0.0,
             _padding: Default::default(),
         }
     }
+    /// Light coming from infinitely far away, like the sun
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Light {
+            kind: DIRECTIONAL,
+            direction: direction.normalise(),
+            ..Self::point(Vec3::zeroes(), color, intensity)
+        }
+    }
+    /// A cone of light, the angles are radians from `direction` to the edge
+    /// of the fully lit and the unlit parts of the cone
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        Light {
+            kind: SPOT,
+            direction: direction.normalise(),
+            inner_cone: inner_angle.cos(),
+            outer_cone: outer_angle.cos(),
+            ..Self::point(position, color, intensity)
+        }
+    }
+    pub const fn with_range(mut self, range: f32) -> Self {
+        self.range = range;
+        self
+    }
+    pub const fn position(&self) -> Vec3 {
+        self.position
+    }
+    pub const fn set_position(&mut self, position: Vec3) {
+        self.position = position;
+    }
+    pub const fn is_directional(&self) -> bool {
+        self.kind == DIRECTIONAL
+    }
+    /// Light space view and projection for a directional light's shadow map,
+    /// covering a box reaching `radius` from `focus` in every direction
+    pub fn shadow_view_projection(&self, focus: Vec3, radius: f32) -> Mat4 {
+        let eye = focus - self.direction * radius;
+        // Any up works as long as it is not parallel to the light
+        let up = if self.direction.y.abs() > 0.99 {
+            Vec3::x()
+        } else {
+            Vec3::y()
+        };
+        let view = Mat4::look_at_rh(eye, focus, up);
+        let projection = Mat4::orthographic_rh(
+            -radius,
+            radius,
+            -radius,
+            radius,
+            0.0,
+            2.0 * radius,
+        );
+        view * projection
+    }
+    pub fn set_direction(&mut self, direction: Vec3) {
+        self.direction = direction.normalise();
+    }
+}
+
+/// Handle to a light in [`Lights`], stays valid until the light is removed.
+/// A new light reusing the slot gets a new generation, so stale handles
+/// resolve to nothing instead of to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LightId {
+    index: usize,
+    generation: u32,
+}
+
+#[derive(Default)]
+struct Slot {
+    /// Bumped every time the light in this slot is removed
+    generation: u32,
+    light: Option<Light>,
+}
+
+/// Every light in the scene, uploaded to the GPU each frame with
+/// [`Gpu::write_lights`](super::Gpu::write_lights)
+pub struct Lights {
+    slots: Vec<Slot>,
+    /// Light reaching everything from every direction, stands in for light
+    /// bouncing around the scene
+    ambient_colour: Vec3,
+    ambient_strength: f32,
+}
+
+impl Default for Lights {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            ambient_colour: Vec3::xyz(1.0),
+            ambient_strength: 0.03,
+        }
+    }
+}
+
+impl Lights {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add(&mut self, light: Light) -> LightId {
+        let index =
+            match self.slots.iter().position(|slot| slot.light.is_none()) {
+                Some(index) => index,
+                None => {
+                    self.slots.push(Slot::default());
+                    self.slots.len() - 1
+                }
+            };
+        let slot = &mut self.slots[index];
+        slot.light = Some(light);
+        LightId {
+            index,
+            generation: slot.generation,
+        }
+    }
+    pub const fn set_ambient(&mut self, colour: Vec3, strength: f32) {
+        self.ambient_colour = colour;
+        self.ambient_strength = strength;
+    }
+    /// Ambient colour scaled by its strength
+    pub fn ambient(&self) -> Vec3 {
+        self.ambient_colour * self.ambient_strength
+    }
+    pub fn remove(&mut self, id: LightId) -> Option<Light> {
+        let slot = self.slot_mut(id)?;
+        let light = slot.light.take();
+        slot.generation += 1;
+        light
+    }
+    pub fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.slot_mut(id)?.light.as_mut()
+    }
+    fn slot_mut(&mut self, id: LightId) -> Option<&mut Slot> {
+        self.slots
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Light> {
+        self.slots.iter().filter_map(|slot| slot.light.as_ref())
+    }
+    /// The light that casts shadows, the first directional light, with its
+    /// index in the order [`Lights::iter`] yields them
+    pub fn shadow_caster(&self) -> Option<(usize, &Light)> {
+        self.iter()
+            .enumerate()
+            .find(|(_, light)| light.is_directional())
+    }
+    /// Move lights attached to entities along with them, the offset turns
+    /// with the entity
+    pub fn follow(&mut self, entities: &[Entity]) {
+        for entity in entities {
+            let Some(attached) = entity.light else {
+                continue;
+            };
+            if let Some(light) = self.get_mut(attached.id) {
+                let offset = entity.rotation().rotate(attached.offset);
+                light.set_position(entity.position() + offset);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(intensity: f32) -> Light {
+        Light::point(Vec3::zeroes(), Vec3::xyz(1.0), intensity)
+    }
+
+    #[test]
+    fn removed_slots_are_reused() {
+        let mut lights = Lights::new();
+        let a = lights.add(light(1.0));
+        let b = lights.add(light(2.0));
+
+        assert_eq!(lights.remove(a).unwrap().intensity, 1.0);
+        assert!(lights.remove(a).is_none());
+        assert_eq!(lights.iter().count(), 1);
+
+        let c = lights.add(light(3.0));
+        assert_eq!(c.index, a.index);
+        assert_ne!(c, a);
+        assert_eq!(lights.get_mut(b).unwrap().intensity, 2.0);
+        assert_eq!(lights.iter().count(), 2);
+
+        // The stale handle does not resolve to the light now in its slot
+        assert!(lights.get_mut(a).is_none());
+        assert!(lights.remove(a).is_none());
+        assert_eq!(lights.get_mut(c).unwrap().intensity, 3.0);
+    }
+
+    #[test]
+    fn first_directional_light_casts_shadows() {
+        let mut lights = Lights::new();
+        assert!(lights.shadow_caster().is_none());
+
+        lights.add(light(1.0));
+        let sun = Light::directional(-Vec3::y(), Vec3::xyz(1.0), 2.0);
+        lights.add(sun);
+        lights.add(Light::directional(Vec3::x(), Vec3::xyz(1.0), 3.0));
+
+        let (index, caster) = lights.shadow_caster().unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(caster.intensity, 2.0);
+    }
+
+    #[test]
+    fn shadow_projection_centres_on_focus() {
+        let sun =
+            Light::directional(Vec3::new(0.0, -1.0, -1.0), Vec3::xyz(1.0), 1.0);
+        let focus = Vec3::new(1.0, 2.0, 3.0);
+        let view_projection = sun.shadow_view_projection(focus, 5.0);
+
+        let centre = view_projection.transform_point(focus);
+        assert!(centre.x.abs() < 1e-5 && centre.y.abs() < 1e-5);
+        assert!((centre.z - 0.5).abs() < 1e-5);
+
+        // Towards the light is nearer, away from it is further
+        let near = view_projection.transform_point(focus - sun.direction * 5.0);
+        let far = view_projection.transform_point(focus + sun.direction * 5.0);
+        assert!(near.z.abs() < 1e-5);
+        assert!((far.z - 1.0).abs() < 1e-5);
+    }
 }
@@ -1,8 +1,10 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use assets::load_glb;
-
-use gpu::Gpu;
 use winit::{dpi::PhysicalSize, window::Window};
 
 use crate::{game::Entity, maths::Vec3};
@@ -11,15 +13,30 @@ mod assets;
 mod camera;
 mod gpu;
 mod light;
+mod post;
+mod watch;
+pub use assets::{AssetCache, AssetError};
 pub use camera::Camera;
+pub use gpu::Gpu;
 pub use gpu::MeshInstance;
 pub use gpu::Vertex;
-pub use light::Light;
+pub use light::{Light, LightId, Lights};
+pub use post::{Tonemapper, Vignette};
+use watch::FileWatch;
+
+const ASSETS_DIR: &str = "assets";
+/// How often dev mode looks for changed models
+const ASSET_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 pub struct State {
     pub window: Arc<Window>,
     pub camera: Camera,
+    pub lights: Lights,
     pub gpu: Gpu,
+    /// Images and materials shared between every model loaded
+    pub assets: AssetCache,
+    /// Set in dev mode to reload models when their files change
+    asset_watch: Option<AssetWatch>,
 }
 
 impl State {
@@ -28,24 +45,28 @@ impl State {
         let window_size = window.inner_size();
 
         let camera = Camera::new(&window_size);
-        let light = Light::new(
-            Vec3::new(0.0, 0.5, 0.5),
-            Vec3::new(1.0, 1.0, 0.0),
-            0.75,
-        );
+        let mut lights = Lights::new();
+        lights.add(scene_light());
 
-        let gpu = Gpu::new(
+        let mut gpu = Gpu::new(
             window.clone(),
             window_size.width,
             window_size.height,
             &camera,
-            &light,
         );
+        // Dev mode, shader and model edits show up without a restart
+        let asset_watch = cfg!(debug_assertions).then(|| {
+            gpu.watch_shaders();
+            AssetWatch::new(ASSETS_DIR, ASSET_POLL_INTERVAL)
+        });
 
         Self {
             window,
             camera,
+            lights,
             gpu,
+            assets: AssetCache::default(),
+            asset_watch,
         }
     }
 
@@ -54,27 +75,187 @@ impl State {
         self.camera.set_aspect_ratio(&size);
     }
 
-    pub fn render(&mut self, entities: &Vec<Entity>) {
-        self.gpu.write_camera(&self.camera.view_perspective_rh());
+    pub fn render(&mut self, entities: &mut [Entity]) {
+        self.gpu.reload_shaders();
+        if let Some(watch) = &mut self.asset_watch {
+            for model in watch.poll(&mut self.assets) {
+                let model = self.gpu.load_model(model);
+                let mesh = self.gpu.get_mesh(model);
+                entities
+                    .iter_mut()
+                    .filter(|entity| entity.mesh == mesh)
+                    .for_each(|entity| {
+                        entity.reload_animation(
+                            self.gpu.skeleton(model),
+                            self.gpu.animation(model),
+                        )
+                    });
+            }
+        }
+        self.gpu.write_camera(&self.camera);
+        self.lights.follow(entities);
+        self.gpu.write_lights(&self.lights, self.camera.target());
 
         let frame = self.gpu.render(entities);
+        log::debug!("{:?}", self.gpu.cull_stats());
         self.window.pre_present_notify();
         frame.present();
     }
 }
 
-pub fn load_assets() -> impl Iterator<Item = assets::Mesh> {
-    [
-        load_glb("assets/BoxTextured.glb"),
-        load_glb("assets/cube.glb"),
-        load_glb("assets/ground.glb"),
-    ]
-    .into_iter()
-    .flatten()
+/// The sun
+pub fn scene_light() -> Light {
+    Light::directional(
+        Vec3::new(0.0, -0.5, -0.5),
+        Vec3::new(1.0, 1.0, 0.0),
+        0.75,
+    )
+}
+
+/// Load every glTF file in `assets/`, each is registered under its file stem.
+/// Files that fail to load are logged and skipped
+pub fn load_assets(
+    cache: &mut AssetCache,
+) -> impl Iterator<Item = assets::Model> + '_ {
+    asset_paths(ASSETS_DIR.as_ref())
+        .into_iter()
+        .filter_map(|path| load_asset(path, cache))
+}
+
+/// Every glTF file in `dir`, sorted so models load in the same order. A
+/// directory that cannot be read is logged and has none
+fn asset_paths(dir: &Path) -> Vec<PathBuf> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) => {
+            log::error!("Cannot read {}: {error}", dir.display());
+            return Vec::new();
+        }
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            matches!(
+                path.extension().and_then(|ext| ext.to_str()),
+                Some("glb" | "gltf")
+            )
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn load_asset(path: PathBuf, cache: &mut AssetCache) -> Option<assets::Model> {
+    match assets::Model::load(&path, cache) {
+        Ok(model) => Some(model),
+        Err(error) => {
+            log::error!("Skipping {}: {error}", path.display());
+            None
+        }
+    }
+}
+
+/// Dev mode, notices glTF files in a directory being exported again or
+/// added so they can be loaded over the models registered under their name
+struct AssetWatch {
+    dir: PathBuf,
+    files: HashMap<PathBuf, FileWatch>,
+    /// Least time between two scans of `dir`
+    interval: Duration,
+    last_scan: Instant,
+}
+
+impl AssetWatch {
+    /// Files already in `dir` only count once they change again
+    fn new(dir: impl Into<PathBuf>, interval: Duration) -> Self {
+        let mut watch = Self {
+            dir: dir.into(),
+            files: HashMap::new(),
+            interval,
+            last_scan: Instant::now(),
+        };
+        watch.changed();
+        watch
+    }
+
+    /// Models whose files changed or appeared since the last scan, none
+    /// until `interval` has passed since it
+    fn poll(&mut self, cache: &mut AssetCache) -> Vec<assets::Model> {
+        if self.last_scan.elapsed() < self.interval {
+            return Vec::new();
+        }
+        self.last_scan = Instant::now();
+        self.changed()
+            .into_iter()
+            .filter_map(|path| load_asset(path, cache))
+            .collect()
+    }
+
+    fn changed(&mut self) -> Vec<PathBuf> {
+        asset_paths(&self.dir)
+            .into_iter()
+            .filter(|path| {
+                self.files
+                    .entry(path.clone())
+                    .or_insert_with(|| FileWatch::new(path))
+                    .changed()
+            })
+            .collect()
+    }
 }
 
-pub enum MeshId {
-    Ground,
-    Cube,
-    CubeGltf,
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn exported_assets_are_reloaded() {
+        let dir =
+            std::env::temp_dir().join(format!("assets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cube = dir.join("cube.glb");
+        fs::copy(Path::new(ASSETS_DIR).join("cube.glb"), &cube).unwrap();
+
+        let mut watch = AssetWatch::new(&dir, Duration::ZERO);
+        let mut cache = AssetCache::default();
+        assert!(watch.poll(&mut cache).is_empty());
+
+        // Exporting again from Blender rewrites the file
+        let file = fs::File::options().append(true).open(&cube).unwrap();
+        let later = file.metadata().unwrap().modified().unwrap()
+            + Duration::from_secs(1);
+        file.set_modified(later).unwrap();
+        fs::copy(&cube, dir.join("crate.glb")).unwrap();
+
+        let names: Vec<String> = watch
+            .poll(&mut cache)
+            .into_iter()
+            .map(|model| model.name)
+            .collect();
+        assert_eq!(names, ["crate", "cube"]);
+        assert!(watch.poll(&mut cache).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn scans_wait_for_the_interval() {
+        let dir = std::env::temp_dir()
+            .join(format!("assets-interval-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut watch = AssetWatch::new(&dir, Duration::from_secs(3600));
+        fs::copy(Path::new(ASSETS_DIR).join("cube.glb"), dir.join("cube.glb"))
+            .unwrap();
+        assert!(watch.poll(&mut AssetCache::default()).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_asset_dir_has_no_assets() {
+        assert!(asset_paths(Path::new("no-such-assets-dir")).is_empty());
+    }
 }
@@ -0,0 +1,411 @@
+use bytemuck::{Pod, Zeroable, bytes_of};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt as _},
+    *,
+};
+
+/// The main pass draws lit colours into this so they can go past 1 and are
+/// only brought into range by tonemapping
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// One fullscreen pass run on the HDR frame after the main pass, reading the
+/// frame so far and drawing the next version of it into `output`. Stages are
+/// chained by [`PostProcess`], anything not last draws into [`HDR_FORMAT`]
+pub trait PostStage {
+    /// Read `input` from now on, called when the stage is added and whenever
+    /// the frames are remade, so bind groups are not made every frame
+    fn bind(&mut self, device: &Device, input: &TextureView);
+    fn draw(&self, encoder: &mut CommandEncoder, output: &TextureView);
+}
+
+/// How HDR colours are squeezed into the 0..1 the screen can show
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tonemapper {
+    /// Filmic, desaturates and rolls off highlights
+    #[default]
+    Aces,
+    /// Keeps hues but flattens bright areas
+    Reinhard,
+}
+
+/// Post processing between the HDR frame and the output, user stages run in
+/// the order they were added and tonemapping always runs last
+pub struct PostProcess {
+    stages: Vec<Box<dyn PostStage>>,
+    tonemap: Tonemap,
+    /// Targets the stages before tonemapping take turns drawing into, only
+    /// made when there are such stages
+    intermediates: Vec<TextureView>,
+    width: u32,
+    height: u32,
+}
+
+impl PostProcess {
+    /// Post processing for frames of `width` by `height` drawn into `hdr`
+    pub fn new(
+        device: &Device,
+        output_format: TextureFormat,
+        width: u32,
+        height: u32,
+        hdr: &TextureView,
+    ) -> Self {
+        Self {
+            stages: Vec::new(),
+            tonemap: Tonemap::new(device, output_format, hdr),
+            intermediates: Vec::new(),
+            width,
+            height,
+        }
+    }
+
+    /// Run `stage` after the ones already added, before tonemapping
+    pub fn push(
+        &mut self,
+        device: &Device,
+        stage: Box<dyn PostStage>,
+        hdr: &TextureView,
+    ) {
+        self.stages.push(stage);
+        self.resize(device, self.width, self.height, hdr);
+    }
+
+    /// Make the targets for frames of the new size, `hdr` is the new frame
+    /// the first stage reads
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        width: u32,
+        height: u32,
+        hdr: &TextureView,
+    ) {
+        self.width = width;
+        self.height = height;
+        self.intermediates = (0..self.stages.len().min(2))
+            .map(|_| create_hdr_texture(device, width, height, 1))
+            .collect();
+        // Each stage reads what the one before drew, tonemapping reads the
+        // last one's
+        let mut input = hdr;
+        for (i, stage) in self.stages.iter_mut().enumerate() {
+            stage.bind(device, input);
+            input = &self.intermediates[i % 2];
+        }
+        self.tonemap.bind(device, input);
+    }
+
+    pub const fn exposure(&self) -> f32 {
+        self.tonemap.settings.exposure
+    }
+
+    pub const fn tonemapper(&self) -> Tonemapper {
+        self.tonemap.tonemapper
+    }
+
+    pub fn set_tonemapping(
+        &mut self,
+        queue: &Queue,
+        exposure: f32,
+        tonemapper: Tonemapper,
+    ) {
+        self.tonemap.set(queue, exposure, tonemapper);
+    }
+
+    /// Run every stage on the HDR frame and write the tonemapped result to
+    /// `output`
+    pub fn draw(&self, encoder: &mut CommandEncoder, output: &TextureView) {
+        for (i, stage) in self.stages.iter().enumerate() {
+            stage.draw(encoder, &self.intermediates[i % 2]);
+        }
+        self.tonemap.draw(encoder, output);
+    }
+}
+
+#[derive(Zeroable, Pod, Copy, Clone)]
+#[repr(C)]
+struct TonemapUniform {
+    /// Every colour is multiplied by this before tonemapping
+    exposure: f32,
+    tonemapper: u32,
+    _padding: [u8; 8],
+}
+
+/// Exposure and tonemapping from HDR to the output format
+struct Tonemap {
+    pipeline: RenderPipeline,
+    layout: BindGroupLayout,
+    /// Binds the frame tonemapping reads, remade when it is
+    bind_group: BindGroup,
+    buffer: Buffer,
+    settings: TonemapUniform,
+    tonemapper: Tonemapper,
+}
+
+impl Tonemap {
+    fn new(
+        device: &Device,
+        format: TextureFormat,
+        input: &TextureView,
+    ) -> Self {
+        let settings = TonemapUniform {
+            exposure: 1.0,
+            tonemapper: 0,
+            _padding: Default::default(),
+        };
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Tonemap"),
+            contents: bytes_of(&settings),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Tonemap"),
+                entries: &[
+                    input_layout_entry(0),
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        count: None,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                    },
+                ],
+            });
+        let shader = device
+            .create_shader_module(include_wgsl!("../../shaders/tonemap.wgsl"));
+        let pipeline =
+            fullscreen_pipeline(device, "Tonemap", &layout, &shader, format);
+
+        let bind_group =
+            create_tonemap_bind_group(device, &layout, &buffer, input);
+
+        Self {
+            pipeline,
+            layout,
+            bind_group,
+            buffer,
+            settings,
+            tonemapper: Tonemapper::default(),
+        }
+    }
+
+    fn set(&mut self, queue: &Queue, exposure: f32, tonemapper: Tonemapper) {
+        self.tonemapper = tonemapper;
+        self.settings.exposure = exposure;
+        self.settings.tonemapper = match tonemapper {
+            Tonemapper::Aces => 0,
+            Tonemapper::Reinhard => 1,
+        };
+        queue.write_buffer(&self.buffer, 0, bytes_of(&self.settings));
+    }
+}
+
+impl PostStage for Tonemap {
+    fn bind(&mut self, device: &Device, input: &TextureView) {
+        self.bind_group = create_tonemap_bind_group(
+            device,
+            &self.layout,
+            &self.buffer,
+            input,
+        );
+    }
+
+    fn draw(&self, encoder: &mut CommandEncoder, output: &TextureView) {
+        fullscreen_pass(
+            encoder,
+            "Tonemap",
+            &self.pipeline,
+            &self.bind_group,
+            output,
+        );
+    }
+}
+
+fn create_tonemap_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    buffer: &Buffer,
+    input: &TextureView,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Tonemap"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(input),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+/// Darkens the frame towards its corners
+pub struct Vignette {
+    pipeline: RenderPipeline,
+    layout: BindGroupLayout,
+    /// [`None`] until [`PostStage::bind`] gives it a frame to read
+    bind_group: Option<BindGroup>,
+}
+
+impl Vignette {
+    pub fn new(device: &Device) -> Self {
+        let layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Vignette"),
+                entries: &[input_layout_entry(0)],
+            });
+        let shader = device
+            .create_shader_module(include_wgsl!("../../shaders/vignette.wgsl"));
+        let pipeline = fullscreen_pipeline(
+            device, "Vignette", &layout, &shader, HDR_FORMAT,
+        );
+        Self {
+            pipeline,
+            layout,
+            bind_group: None,
+        }
+    }
+}
+
+impl PostStage for Vignette {
+    fn bind(&mut self, device: &Device, input: &TextureView) {
+        self.bind_group =
+            Some(device.create_bind_group(&BindGroupDescriptor {
+                label: Some("Vignette"),
+                layout: &self.layout,
+                entries: &[BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(input),
+                }],
+            }));
+    }
+
+    fn draw(&self, encoder: &mut CommandEncoder, output: &TextureView) {
+        let bind_group = self
+            .bind_group
+            .as_ref()
+            .expect("PostProcess binds stages before drawing them");
+        fullscreen_pass(
+            encoder,
+            "Vignette",
+            &self.pipeline,
+            bind_group,
+            output,
+        );
+    }
+}
+
+/// Colour target the main pass and post stages draw HDR into, sampled by
+/// the stage after unless it is multisampled and only resolved from
+pub fn create_hdr_texture(
+    device: &Device,
+    width: u32,
+    height: u32,
+    sample_count: u32,
+) -> TextureView {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("HDR"),
+        size: Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: if sample_count == 1 {
+            TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING
+        } else {
+            TextureUsages::RENDER_ATTACHMENT
+        },
+        view_formats: &[],
+    });
+    texture.create_view(&Default::default())
+}
+
+/// The frame a stage reads, loaded texel by texel so no sampler is needed
+pub fn input_layout_entry(binding: u32) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::FRAGMENT,
+        count: None,
+        ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: false },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        },
+    }
+}
+
+/// Pipeline for a shader drawing one fullscreen triangle from `vs_main`
+/// with no vertex buffers
+pub fn fullscreen_pipeline(
+    device: &Device,
+    label: &str,
+    layout: &BindGroupLayout,
+    shader: &ShaderModule,
+    format: TextureFormat,
+) -> RenderPipeline {
+    let pipeline_layout =
+        device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &[layout],
+            push_constant_ranges: &[],
+        });
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: None,
+            compilation_options: Default::default(),
+            buffers: &[],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: None,
+            compilation_options: Default::default(),
+            targets: &[Some(format.into())],
+        }),
+        primitive: PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Draw the fullscreen triangle into `output`, overwriting all of it
+pub fn fullscreen_pass(
+    encoder: &mut CommandEncoder,
+    label: &str,
+    pipeline: &RenderPipeline,
+    bind_group: &BindGroup,
+    output: &TextureView,
+) {
+    let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: output,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(Color::BLACK),
+                store: StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..3, 0..1);
+}
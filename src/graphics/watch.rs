@@ -0,0 +1,58 @@
+use std::{fs, path::PathBuf, time::SystemTime};
+
+/// Notices a file changing on disk by polling its modification time
+pub struct FileWatch {
+    path: PathBuf,
+    /// When the file was last seen changing, [`None`] before it is first seen
+    modified: Option<SystemTime>,
+}
+
+impl FileWatch {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            modified: None,
+        }
+    }
+
+    /// Whether the file changed since the last call, the first call after
+    /// the file appears counts as a change. A missing file never changes
+    pub fn changed(&mut self) -> bool {
+        let modified = fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn changes_are_seen_once() {
+        let path = std::env::temp_dir()
+            .join(format!("watch-{}.wgsl", std::process::id()));
+        let mut watch = FileWatch::new(&path);
+        assert!(!watch.changed());
+
+        let file = File::create(&path).unwrap();
+        assert!(watch.changed());
+        assert!(!watch.changed());
+
+        let later = file.metadata().unwrap().modified().unwrap()
+            + Duration::from_secs(1);
+        file.set_modified(later).unwrap();
+        assert!(watch.changed());
+        assert!(!watch.changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watch.changed());
+    }
+}
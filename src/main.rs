@@ -2,6 +2,7 @@ use std::{f32::consts::PI, time::Instant};
 
 use game::Game;
 use input::Input;
+use maths::{Quat, Vec2, Vec3};
 use winit::{
     application::ApplicationHandler,
     dpi::PhysicalSize,
@@ -11,12 +12,13 @@ use winit::{
     window::{Window, WindowId},
 };
 
+mod animation;
 mod game;
 mod graphics;
 mod input;
 mod maths;
 mod physics;
-use graphics::State;
+use graphics::{AssetCache, Camera, Gpu, Lights, State, Tonemapper, Vignette};
 
 struct App {
     state: Option<State>,
@@ -40,10 +42,11 @@ impl App {
     fn init(&mut self, window: Window) {
         let mut state = State::new(window);
 
-        let meshes = graphics::load_assets();
-        state.gpu.load_meshes(meshes);
+        state
+            .gpu
+            .load_models(graphics::load_assets(&mut state.assets));
 
-        self.game.init(&state);
+        self.game.init(&mut state.gpu, &mut state.lights).unwrap();
         self.state = Some(state)
     }
 
@@ -54,7 +57,7 @@ impl App {
 
     #[inline(always)]
     fn render(&mut self) {
-        self.state.as_mut().unwrap().render(&self.game.entities);
+        self.state.as_mut().unwrap().render(&mut self.game.entities);
     }
 
     #[inline(always)]
@@ -64,24 +67,45 @@ impl App {
         }
     }
 
-    fn run_input(&mut self, event_loop: &ActiveEventLoop) {
-        let player = &mut self.game.entities[1];
-        let camera = &mut self.state.as_mut().unwrap().camera;
+    fn move_player(&mut self) {
+        let Some(player) = self.game.player_mut() else {
+            return;
+        };
+        // Along x and z, normalised so moving diagonally is no faster
+        let mut walk = Vec2::zeroes();
         if self.input.is_pressed(KeyCode::KeyW) {
-            player.move_x(self.delta_time, 5.0);
+            walk.x += 1.0;
         }
         if self.input.is_pressed(KeyCode::KeyA) {
-            player.move_z(self.delta_time, -5.0);
+            walk.y -= 1.0;
         }
         if self.input.is_pressed(KeyCode::KeyS) {
-            player.move_x(self.delta_time, -5.0);
+            walk.x -= 1.0;
         }
         if self.input.is_pressed(KeyCode::KeyD) {
-            player.move_z(self.delta_time, 5.0);
+            walk.y += 1.0;
         }
+        let walk = walk.normalise() * 5.0;
+        player.move_x(self.delta_time, walk.x);
+        player.move_z(self.delta_time, walk.y);
         if self.input.is_pressed(KeyCode::Space) {
             player.jump(self.delta_time, 2000.0);
         }
+        if self.input.is_pressed(KeyCode::KeyQ) {
+            player
+                .rotate(Quat::from_axis_angle(Vec3::y(), self.delta_time * PI));
+        }
+        if self.input.is_pressed(KeyCode::KeyE) {
+            player.rotate(Quat::from_axis_angle(
+                Vec3::y(),
+                -self.delta_time * PI,
+            ));
+        }
+    }
+
+    fn run_input(&mut self, event_loop: &ActiveEventLoop) {
+        self.move_player();
+        let camera = &mut self.state.as_mut().unwrap().camera;
         if self.input.is_pressed(KeyCode::ArrowUp) {
             camera.forward(self.delta_time, 10.0)
         }
@@ -94,6 +118,12 @@ impl App {
         if self.input.is_pressed(KeyCode::ArrowRight) {
             camera.strafe(self.delta_time, 10.0);
         }
+        if self.input.is_pressed(KeyCode::KeyY) {
+            camera.rotate_x(self.delta_time, PI / 2.0)
+        }
+        if self.input.is_pressed(KeyCode::KeyI) {
+            camera.rotate_x(self.delta_time, -PI / 2.0)
+        }
         if self.input.is_pressed(KeyCode::KeyU) {
             camera.rotate_z(self.delta_time, PI / 2.0)
         }
@@ -106,13 +136,44 @@ impl App {
         if self.input.is_pressed(KeyCode::KeyK) {
             camera.rotate_y(self.delta_time, PI / 2.0)
         }
+        let gpu = &mut self.state.as_mut().unwrap().gpu;
+        if self.input.is_pressed(KeyCode::Equal) {
+            gpu.set_exposure(gpu.exposure() * 2f32.powf(self.delta_time));
+        }
+        if self.input.is_pressed(KeyCode::Minus) {
+            gpu.set_exposure(gpu.exposure() / 2f32.powf(self.delta_time));
+        }
         if self.input.is_pressed(KeyCode::Escape) {
             event_loop.exit();
         }
     }
 
+    /// Step to the next sample count the adapter supports, wrapping back
+    /// round to no MSAA
+    fn cycle_msaa(&mut self) {
+        let gpu = &mut self.state().gpu;
+        let supported = gpu.supported_sample_counts();
+        let current = supported
+            .iter()
+            .position(|&count| count == gpu.sample_count())
+            .unwrap_or(0);
+        let next = supported[(current + 1) % supported.len()];
+        gpu.set_sample_count(next).unwrap();
+        log::info!("MSAA {next}x");
+    }
+
     fn run_game(&mut self) {
         self.game.update(self.delta_time);
+        self.game
+            .update_lights(&mut self.state.as_mut().unwrap().lights);
+        // The pickup spins whenever it is not hopping
+        if let Some(pickup) = self.game.pickup_mut()
+            && let Some(animation) = &mut pickup.player
+            && animation.finished()
+            && let Err(error) = animation.play("spin", true)
+        {
+            log::warn!("{error}");
+        }
     }
 
     fn update_delta_time(&mut self) {
@@ -154,6 +215,46 @@ impl ApplicationHandler for App {
             }
             WindowEvent::KeyboardInput { ref event, .. } => {
                 self.input.handle_keyboard(event);
+                if event.state.is_pressed()
+                    && !event.repeat
+                    && event.physical_key == KeyCode::KeyM
+                {
+                    self.cycle_msaa();
+                }
+                if event.state.is_pressed()
+                    && !event.repeat
+                    && event.physical_key == KeyCode::KeyT
+                {
+                    let gpu = &mut self.state().gpu;
+                    gpu.set_tonemapper(match gpu.tonemapper() {
+                        Tonemapper::Aces => Tonemapper::Reinhard,
+                        Tonemapper::Reinhard => Tonemapper::Aces,
+                    });
+                }
+                if event.state.is_pressed()
+                    && !event.repeat
+                    && event.physical_key == KeyCode::KeyP
+                    && let Some(pillar) = self.game.pillar_mut()
+                {
+                    // The pillar goes back to standing straight
+                    pillar.stop();
+                }
+                if event.state.is_pressed()
+                    && !event.repeat
+                    && event.physical_key == KeyCode::KeyO
+                    && let Some(pickup) = self.game.pickup_mut()
+                    && let Some(animation) = &mut pickup.player
+                    && let Err(error) = animation.play("hop", false)
+                {
+                    log::warn!("{error}");
+                }
+                if event.state.is_pressed()
+                    && !event.repeat
+                    && event.physical_key == KeyCode::KeyL
+                {
+                    let lights = &mut self.state.as_mut().unwrap().lights;
+                    self.game.toggle_torch(lights);
+                }
             }
             WindowEvent::MouseWheel { delta, .. } => match delta {
                 MouseScrollDelta::LineDelta(_, direction) => {
@@ -182,8 +283,85 @@ impl ApplicationHandler for App {
     }
 }
 
+/// Render the opening frame of the game without a window and save it as PNG
+fn capture(path: &str) {
+    let size = PhysicalSize::new(1280, 720);
+    let camera = Camera::new(&size);
+    let mut gpu = Gpu::headless(size.width, size.height, &camera)
+        .expect("no wgpu adapter available");
+    gpu.push_post_stage(Box::new(Vignette::new(gpu.device())));
+    gpu.load_models(graphics::load_assets(&mut AssetCache::default()));
+
+    let mut lights = Lights::new();
+    lights.add(graphics::scene_light());
+
+    let mut game = Game::new();
+    game.init(&mut gpu, &mut lights).unwrap();
+    game.update_lights(&mut lights);
+    lights.follow(&game.entities);
+
+    gpu.write_camera(&camera);
+    gpu.write_lights(&lights, camera.target());
+    gpu.render_to_image(&game.entities).save(path).unwrap();
+}
+
+/// Render a grid of `cubes` cubes without a window and print the average
+/// frame time with and without batching
+fn benchmark(cubes: usize) {
+    const FRAMES: u32 = 100;
+
+    let size = PhysicalSize::new(1280, 720);
+    let mut camera = Camera::new(&size);
+    let side = (cubes as f32).sqrt();
+    camera.set_position(Vec3::new(-side, side, -side));
+    let mut gpu = Gpu::headless(size.width, size.height, &camera)
+        .expect("no wgpu adapter available");
+    gpu.load_models(graphics::load_assets(&mut AssetCache::default()));
+
+    let mut lights = Lights::new();
+    lights.add(graphics::scene_light());
+
+    let mut game = Game::new();
+    game.init_benchmark(&gpu, cubes).unwrap();
+
+    gpu.write_camera(&camera);
+    gpu.write_lights(&lights, camera.target());
+    let frame_time = |gpu: &mut Gpu, batching| {
+        gpu.set_batching(batching);
+        // Warm up so pipeline and buffer creation are not timed
+        gpu.render_offscreen(&game.entities);
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            gpu.render_offscreen(&game.entities);
+        }
+        (start.elapsed() / FRAMES).as_secs_f64() * 1000.0
+    };
+    let batched = frame_time(&mut gpu, true);
+    let unbatched = frame_time(&mut gpu, false);
+    let stats = gpu.cull_stats();
+    println!(
+        "{cubes} cubes: {batched:.2} ms per frame batched, {unbatched:.2} ms \
+         unbatched, {} drawn, {} culled",
+        stats.drawn, stats.culled,
+    );
+}
+
 fn main() {
     env_logger::init();
+
+    let args: Vec<String> = std::env::args().collect();
+    match args.as_slice() {
+        [_, flag, path] if flag == "--capture" => {
+            capture(path);
+            return;
+        }
+        [_, flag, cubes] if flag == "--benchmark" => {
+            benchmark(cubes.parse().expect("cube count should be a number"));
+            return;
+        }
+        _ => {}
+    }
+
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
     event_loop.run_app(&mut App::new()).unwrap();
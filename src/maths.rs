@@ -73,6 +73,180 @@ impl Mat4 {
             w: Vec4::new(0.0, 0.0, 0.0, 1.0),
         }
     }
+    pub const fn from_rotation(rotation: Quat) -> Self {
+        let Quat { x, y, z, w } = rotation;
+        Self {
+            x: Vec4::new(
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y + w * z),
+                2.0 * (x * z - w * y),
+                0.0,
+            ),
+            y: Vec4::new(
+                2.0 * (x * y - w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z + w * x),
+                0.0,
+            ),
+            z: Vec4::new(
+                2.0 * (x * z + w * y),
+                2.0 * (y * z - w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ),
+            w: Vec4::new(0.0, 0.0, 0.0, 1.0),
+        }
+    }
+    /// Scale, then rotate, then translate
+    pub fn from_trs(translation: Vec3, rotation: Quat, scale: Vec3) -> Self {
+        // Our multiplication applies the left hand side first
+        Self::from_scaling(scale)
+            * Self::from_rotation(rotation)
+            * Self::from_translation(translation)
+    }
+    /// View matrix for an eye at `eye` looking at `target`, right handed
+    pub fn look_at_rh(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        let forward = (target - eye).normalise();
+        let right = forward.cross(&up).normalise();
+        let up = right.cross(&forward).normalise();
+
+        Self {
+            x: Vec4::new(right.x, up.x, -forward.x, 0.0),
+            y: Vec4::new(right.y, up.y, -forward.y, 0.0),
+            z: Vec4::new(right.z, up.z, -forward.z, 0.0),
+            w: Vec4::new(
+                -right.dot(&eye),
+                -up.dot(&eye),
+                forward.dot(&eye),
+                1.0,
+            ),
+        }
+    }
+    /// Right handed perspective projection, `fovy` is the vertical field of
+    /// view in radians. Depth is mapped to 0..1 the way wgpu expects
+    pub fn perspective_rh(fovy: f32, aspect: f32, near: f32, far: f32) -> Self {
+        let focal_length = 1.0 / (fovy / 2.0).tan();
+        let range = near - far;
+        Self {
+            x: Vec4::new(focal_length / aspect, 0.0, 0.0, 0.0),
+            y: Vec4::new(0.0, focal_length, 0.0, 0.0),
+            z: Vec4::new(0.0, 0.0, far / range, -1.0),
+            w: Vec4::new(0.0, 0.0, near * far / range, 0.0),
+        }
+    }
+    /// Right handed orthographic projection with depth mapped to 0..1 the way
+    /// wgpu expects
+    pub const fn orthographic_rh(
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        near: f32,
+        far: f32,
+    ) -> Self {
+        let width = right - left;
+        let height = top - bottom;
+        let depth = far - near;
+        Self {
+            x: Vec4::new(2.0 / width, 0.0, 0.0, 0.0),
+            y: Vec4::new(0.0, 2.0 / height, 0.0, 0.0),
+            z: Vec4::new(0.0, 0.0, -1.0 / depth, 0.0),
+            w: Vec4::new(
+                -(right + left) / width,
+                -(top + bottom) / height,
+                -near / depth,
+                1.0,
+            ),
+        }
+    }
+    /// Transform a point, the translation column is applied
+    pub const fn transform_point(&self, point: Vec3) -> Vec3 {
+        Vec3::new(
+            self.x.x * point.x
+                + self.y.x * point.y
+                + self.z.x * point.z
+                + self.w.x,
+            self.x.y * point.x
+                + self.y.y * point.y
+                + self.z.y * point.z
+                + self.w.y,
+            self.x.z * point.x
+                + self.y.z * point.y
+                + self.z.z * point.z
+                + self.w.z,
+        )
+    }
+    pub const fn determinant(&self) -> f32 {
+        let [s, c] = self.sub_determinants();
+        s[0] * c[5] - s[1] * c[4] + s[2] * c[3] + s[3] * c[2] - s[4] * c[1]
+            + s[5] * c[0]
+    }
+    /// 2x2 determinants of the first two and last two columns, shared by
+    /// the determinant and the inverse
+    const fn sub_determinants(&self) -> [[f32; 6]; 2] {
+        let (a, b, c, d) = (self.x, self.y, self.z, self.w);
+        [
+            [
+                a.x * b.y - b.x * a.y,
+                a.x * b.z - b.x * a.z,
+                a.x * b.w - b.x * a.w,
+                a.y * b.z - b.y * a.z,
+                a.y * b.w - b.y * a.w,
+                a.z * b.w - b.z * a.w,
+            ],
+            [
+                c.x * d.y - d.x * c.y,
+                c.x * d.z - d.x * c.z,
+                c.x * d.w - d.x * c.w,
+                c.y * d.z - d.y * c.z,
+                c.y * d.w - d.y * c.w,
+                c.z * d.w - d.z * c.w,
+            ],
+        ]
+    }
+    /// [`None`] when the matrix is singular, a scale of zero for example
+    pub const fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det.abs() < f32::EPSILON * f32::EPSILON {
+            return None;
+        }
+        let inv = 1.0 / det;
+        let [s, c] = self.sub_determinants();
+        let (a, b, m, d) = (self.x, self.y, self.z, self.w);
+
+        Some(Self {
+            x: Vec4::new(
+                (b.y * c[5] - b.z * c[4] + b.w * c[3]) * inv,
+                (-a.y * c[5] + a.z * c[4] - a.w * c[3]) * inv,
+                (d.y * s[5] - d.z * s[4] + d.w * s[3]) * inv,
+                (-m.y * s[5] + m.z * s[4] - m.w * s[3]) * inv,
+            ),
+            y: Vec4::new(
+                (-b.x * c[5] + b.z * c[2] - b.w * c[1]) * inv,
+                (a.x * c[5] - a.z * c[2] + a.w * c[1]) * inv,
+                (-d.x * s[5] + d.z * s[2] - d.w * s[1]) * inv,
+                (m.x * s[5] - m.z * s[2] + m.w * s[1]) * inv,
+            ),
+            z: Vec4::new(
+                (b.x * c[4] - b.y * c[2] + b.w * c[0]) * inv,
+                (-a.x * c[4] + a.y * c[2] - a.w * c[0]) * inv,
+                (d.x * s[4] - d.y * s[2] + d.w * s[0]) * inv,
+                (-m.x * s[4] + m.y * s[2] - m.w * s[0]) * inv,
+            ),
+            w: Vec4::new(
+                (-b.x * c[3] + b.y * c[1] - b.z * c[0]) * inv,
+                (a.x * c[3] - a.y * c[1] + a.z * c[0]) * inv,
+                (-d.x * s[3] + d.y * s[1] - d.z * s[0]) * inv,
+                (m.x * s[3] - m.y * s[1] + m.z * s[0]) * inv,
+            ),
+        })
+    }
+    /// Inverse transpose, transforms normals so they stay perpendicular to
+    /// surfaces under non-uniform scaling. Singular matrices give identity
+    pub fn normal_matrix(&self) -> Self {
+        self.inverse()
+            .map_or(Self::identity(), |inverse| inverse.transpose())
+    }
     pub const fn transpose(self) -> Mat4 {
         Mat4 {
             x: Vec4::new(self.x.x, self.y.x, self.z.x, self.w.x),
@@ -117,6 +291,33 @@ impl std::ops::Mul<Mat4> for Mat4 {
     }
 }
 
+impl std::ops::Mul<Vec4> for Mat4 {
+    type Output = Vec4;
+
+    /// The vector transformed by the matrix, `(a * b) * v` is `b * (a * v)`
+    fn mul(self, rhs: Vec4) -> Vec4 {
+        let rows = self.transpose();
+        Vec4::new(
+            rows.x.dot(rhs),
+            rows.y.dot(rhs),
+            rows.z.dot(rhs),
+            rows.w.dot(rhs),
+        )
+    }
+}
+
+impl From<[[f32; 4]; 4]> for Mat4 {
+    /// From an array of columns, the layout glTF uses
+    fn from(value: [[f32; 4]; 4]) -> Self {
+        Self {
+            x: value[0].into(),
+            y: value[1].into(),
+            z: value[2].into(),
+            w: value[3].into(),
+        }
+    }
+}
+
 #[derive(Pod, Zeroable, Copy, Clone, Default, Debug, PartialEq)]
 #[repr(C)]
 pub struct Vec4 {
@@ -138,8 +339,14 @@ impl Vec4 {
     }
 }
 
+impl From<[f32; 4]> for Vec4 {
+    fn from(value: [f32; 4]) -> Self {
+        Self::new(value[0], value[1], value[2], value[3])
+    }
+}
+
 #[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable, Debug)]
+#[derive(Clone, Copy, Pod, Zeroable, Debug, PartialEq)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -182,6 +389,13 @@ impl Vec3 {
         }
         self / len
     }
+    pub fn distance(&self, rhs: &Self) -> f32 {
+        (self - rhs).len()
+    }
+    /// `self` at 0 and `rhs` at 1, `t` outside 0..1 carries on past them
+    pub fn lerp(&self, rhs: &Self, t: f32) -> Self {
+        *self + (*rhs - *self) * t
+    }
 }
 
 impl From<[f32; 3]> for Vec3 {
@@ -215,6 +429,17 @@ impl core::ops::Div<f32> for &Vec3 {
         }
     }
 }
+impl core::ops::Div<f32> for Vec3 {
+    type Output = Vec3;
+
+    fn div(self, rhs: f32) -> Self::Output {
+        Self {
+            x: self.x / rhs,
+            y: self.y / rhs,
+            z: self.z / rhs,
+        }
+    }
+}
 impl core::ops::Mul<Vec3> for Mat3 {
     type Output = Vec3;
 
@@ -270,6 +495,18 @@ impl core::ops::Mul<f32> for Vec3 {
         }
     }
 }
+impl core::ops::Mul for Vec3 {
+    type Output = Self;
+
+    /// Component by component
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self {
+            x: self.x * rhs.x,
+            y: self.y * rhs.y,
+            z: self.z * rhs.z,
+        }
+    }
+}
 impl core::ops::AddAssign for Vec3 {
     fn add_assign(&mut self, rhs: Self) {
         self.x += rhs.x;
@@ -277,11 +514,391 @@ impl core::ops::AddAssign for Vec3 {
         self.z += rhs.z;
     }
 }
+impl core::ops::SubAssign for Vec3 {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.x -= rhs.x;
+        self.y -= rhs.y;
+        self.z -= rhs.z;
+    }
+}
+impl core::ops::MulAssign<f32> for Vec3 {
+    fn mul_assign(&mut self, rhs: f32) {
+        self.x *= rhs;
+        self.y *= rhs;
+        self.z *= rhs;
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, Default, Debug, PartialEq)]
+pub struct Vec2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Vec2 {
+    pub const fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+    pub const fn zeroes() -> Self {
+        Self::new(0.0, 0.0)
+    }
+    pub const fn dot(&self, rhs: &Self) -> f32 {
+        (self.x * rhs.x) + (self.y * rhs.y)
+    }
+    pub fn len(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+    pub fn normalise(&self) -> Self {
+        let len = self.len();
+        if len == 0.0 {
+            return Vec2::zeroes();
+        }
+        *self / len
+    }
+}
+
+impl From<[f32; 2]> for Vec2 {
+    fn from(value: [f32; 2]) -> Self {
+        Self::new(value[0], value[1])
+    }
+}
+impl core::ops::Neg for Vec2 {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self::new(-self.x, -self.y)
+    }
+}
+impl core::ops::Add for Vec2 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+impl core::ops::Sub for Vec2 {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+impl core::ops::Mul<f32> for Vec2 {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        Self::new(self.x * rhs, self.y * rhs)
+    }
+}
+impl core::ops::Mul for Vec2 {
+    type Output = Self;
+
+    /// Component by component
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self::new(self.x * rhs.x, self.y * rhs.y)
+    }
+}
+impl core::ops::Div<f32> for Vec2 {
+    type Output = Self;
+
+    fn div(self, rhs: f32) -> Self::Output {
+        Self::new(self.x / rhs, self.y / rhs)
+    }
+}
+impl core::ops::AddAssign for Vec2 {
+    fn add_assign(&mut self, rhs: Self) {
+        self.x += rhs.x;
+        self.y += rhs.y;
+    }
+}
+impl core::ops::SubAssign for Vec2 {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.x -= rhs.x;
+        self.y -= rhs.y;
+    }
+}
+impl core::ops::MulAssign<f32> for Vec2 {
+    fn mul_assign(&mut self, rhs: f32) {
+        self.x *= rhs;
+        self.y *= rhs;
+    }
+}
+
+/// Rotation as a unit quaternion, `w` is the real part
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Quat {
+    pub const fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Self { x, y, z, w }
+    }
+    pub const fn identity() -> Self {
+        Self::new(0.0, 0.0, 0.0, 1.0)
+    }
+    /// Turn `angle` radians about `axis`, anticlockwise looking down the
+    /// axis towards the origin
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Self {
+        let axis = axis.normalise();
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Self::new(axis.x * sin, axis.y * sin, axis.z * sin, cos)
+    }
+    /// Turn `x` radians about the x axis, then `y` about y, then `z` about z
+    pub fn from_euler(x: f32, y: f32, z: f32) -> Self {
+        Self::from_axis_angle(Vec3::x(), x)
+            * Self::from_axis_angle(Vec3::y(), y)
+            * Self::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), z)
+    }
+    pub const fn dot(&self, rhs: &Self) -> f32 {
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z + self.w * rhs.w
+    }
+    pub fn rotate(&self, vector: Vec3) -> Vec3 {
+        let axis = Vec3::new(self.x, self.y, self.z);
+        let t = axis.cross(&vector) * 2.0;
+        vector + t * self.w + axis.cross(&t)
+    }
+    /// Identity for a quaternion with no length
+    pub fn normalise(&self) -> Self {
+        let len = self.dot(self).sqrt();
+        if len == 0.0 {
+            return Self::identity();
+        }
+        *self * (1.0 / len)
+    }
+    /// Spherical interpolation along the shorter way round
+    pub fn slerp(&self, rhs: &Self, t: f32) -> Self {
+        let mut cos = self.dot(rhs);
+        // q and -q are the same rotation, flip to take the short path
+        let rhs = if cos < 0.0 {
+            cos = -cos;
+            *rhs * -1.0
+        } else {
+            *rhs
+        };
+        // Nearly parallel, linear is accurate and avoids dividing by ~0
+        if cos > 0.9995 {
+            return (*self * (1.0 - t) + rhs * t).normalise();
+        }
+        let angle = cos.acos();
+        let sin = angle.sin();
+        *self * (((1.0 - t) * angle).sin() / sin)
+            + rhs * ((t * angle).sin() / sin)
+    }
+}
+
+impl From<[f32; 4]> for Quat {
+    /// From `[x, y, z, w]`, the order glTF uses
+    fn from(value: [f32; 4]) -> Self {
+        Self::new(value[0], value[1], value[2], value[3])
+    }
+}
+impl core::ops::Add for Quat {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self::new(
+            self.x + rhs.x,
+            self.y + rhs.y,
+            self.z + rhs.z,
+            self.w + rhs.w,
+        )
+    }
+}
+impl core::ops::Mul for Quat {
+    type Output = Self;
+
+    /// Like [`Mat4`], the left hand side is applied first
+    fn mul(self, rhs: Self) -> Self::Output {
+        let (a, b) = (rhs, self);
+        Self::new(
+            a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+            a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+        )
+    }
+}
+impl core::ops::MulAssign for Quat {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+impl core::ops::Mul<f32> for Quat {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        Self::new(self.x * rhs, self.y * rhs, self.z * rhs, self.w * rhs)
+    }
+}
+
+/// Axis aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// The smallest box holding every point, a box around the origin with no
+    /// size when there are none
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Self {
+                min: Vec3::zeroes(),
+                max: Vec3::zeroes(),
+            };
+        };
+        points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |aabb, point| {
+                aabb.union(&Self {
+                    min: point,
+                    max: point,
+                })
+            },
+        )
+    }
+    pub const fn union(&self, other: &Self) -> Self {
+        Self {
+            min: Vec3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Vec3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+    pub fn centre(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+    pub const fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vec3::new(min.x, min.y, min.z),
+            Vec3::new(max.x, min.y, min.z),
+            Vec3::new(min.x, max.y, min.z),
+            Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z),
+            Vec3::new(max.x, min.y, max.z),
+            Vec3::new(min.x, max.y, max.z),
+            Vec3::new(max.x, max.y, max.z),
+        ]
+    }
+    /// The box holding this one once `matrix` is applied to it
+    pub fn transform(&self, matrix: &Mat4) -> Self {
+        Self::from_points(
+            self.corners().map(|corner| matrix.transform_point(corner)),
+        )
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn assert_mat4_near(a: Mat4, b: Mat4) {
+        for (a, b) in [(a.x, b.x), (a.y, b.y), (a.z, b.z), (a.w, b.w)] {
+            for (a, b) in [(a.x, b.x), (a.y, b.y), (a.z, b.z), (a.w, b.w)] {
+                assert!((a - b).abs() < 1e-5, "{a} != {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let matrix = Mat4 {
+            x: Vec4::new(2.0, 0.0, 1.0, 0.0),
+            y: Vec4::new(1.0, 3.0, 0.0, 0.0),
+            z: Vec4::new(0.0, 1.0, 4.0, 0.0),
+            w: Vec4::new(5.0, -2.0, 7.0, 1.0),
+        };
+        let inverse = matrix.inverse().unwrap();
+
+        assert_mat4_near(matrix * inverse, Mat4::identity());
+        assert_mat4_near(inverse * matrix, Mat4::identity());
+        assert!(
+            (matrix.determinant() * inverse.determinant() - 1.0).abs() < 1e-5
+        );
+    }
+
+    #[test]
+    fn singular_matrix_has_no_inverse() {
+        let flat = Mat4::from_scaling(Vec3::new(1.0, 0.0, 1.0));
+        assert_eq!(flat.determinant(), 0.0);
+        assert!(flat.inverse().is_none());
+    }
+
+    #[test]
+    fn aabb_holds_its_points() {
+        let aabb = Aabb::from_points([
+            Vec3::new(1.0, -2.0, 0.5),
+            Vec3::new(-1.0, 3.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+        ]);
+        assert_eq!(aabb.min, Vec3::new(-1.0, -2.0, 0.0));
+        assert_eq!(aabb.max, Vec3::new(1.0, 3.0, 2.0));
+    }
+
+    #[test]
+    fn aabb_follows_its_transform() {
+        let aabb = Aabb::from_points([Vec3::xyz(-1.0), Vec3::xyz(1.0)]);
+        let matrix = Mat4::from_scaling(Vec3::new(2.0, 1.0, 1.0))
+            * Mat4::from_translation(Vec3::new(5.0, 0.0, 0.0));
+
+        let moved = aabb.transform(&matrix);
+        assert_eq!(moved.min, Vec3::new(3.0, -1.0, -1.0));
+        assert_eq!(moved.max, Vec3::new(7.0, 1.0, 1.0));
+    }
+
+    fn assert_vec3_near(a: Vec3, b: Vec3) {
+        assert!((a - b).len() < 1e-5, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn quat_rotation_matrix() {
+        // A quarter turn about z, x goes to y
+        let half = std::f32::consts::FRAC_PI_4;
+        let quarter = Quat::new(0.0, 0.0, half.sin(), half.cos());
+        let matrix = Mat4::from_rotation(quarter);
+        assert_vec3_near(matrix.transform_point(Vec3::x()), Vec3::y());
+
+        // Scaled first and translated last
+        let trs =
+            Mat4::from_trs(Vec3::new(0.0, 0.0, 5.0), quarter, Vec3::xyz(2.0));
+        assert_vec3_near(
+            trs.transform_point(Vec3::x()),
+            Vec3::new(0.0, 2.0, 5.0),
+        );
+    }
+
+    #[test]
+    fn slerp_takes_the_short_way() {
+        let half = std::f32::consts::FRAC_PI_4;
+        let quarter = Quat::new(0.0, 0.0, half.sin(), half.cos());
+
+        let eighth = Quat::identity().slerp(&quarter, 0.5);
+        let angle = Mat4::from_rotation(eighth).transform_point(Vec3::x());
+        let expected = std::f32::consts::FRAC_PI_4;
+        assert_vec3_near(angle, Vec3::new(expected.cos(), expected.sin(), 0.0));
+
+        // -quarter is the same rotation and must give the same answer
+        let flipped = Quat::identity().slerp(&(quarter * -1.0), 0.5);
+        assert!((flipped.dot(&eighth).abs() - 1.0).abs() < 1e-5);
+    }
+
     #[test]
     fn test_mat4_identity_multiplication() {
         let mat_a = Mat4 {
@@ -348,4 +965,253 @@ mod tests {
 
         assert_eq!(result, expected);
     }
+
+    fn assert_quat_near(a: Quat, b: Quat) {
+        // q and -q are the same rotation
+        assert!((a.dot(&b).abs() - 1.0).abs() < 1e-5, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn axis_angle_turns_anticlockwise() {
+        let quarter = std::f32::consts::FRAC_PI_2;
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        let about_z = Quat::from_axis_angle(z * 3.0, quarter);
+        assert_vec3_near(about_z.rotate(Vec3::x()), Vec3::y());
+        let about_x = Quat::from_axis_angle(Vec3::x(), quarter);
+        assert_vec3_near(about_x.rotate(Vec3::y()), z);
+        let about_y = Quat::from_axis_angle(Vec3::y(), quarter);
+        assert_vec3_near(about_y.rotate(z), Vec3::x());
+
+        // The matrix agrees with rotating directly
+        let point = Vec3::new(1.0, -2.0, 3.0);
+        let turn = Quat::from_axis_angle(Vec3::new(1.0, 1.0, 0.0), 1.2);
+        assert_vec3_near(
+            Mat4::from_rotation(turn).transform_point(point),
+            turn.rotate(point),
+        );
+    }
+
+    #[test]
+    fn quat_multiplication_applies_the_left_first() {
+        let a = Quat::from_axis_angle(Vec3::x(), 0.7);
+        let b = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 2.0), -1.3);
+        let point = Vec3::new(0.5, 2.0, -1.0);
+
+        assert_vec3_near((a * b).rotate(point), b.rotate(a.rotate(point)));
+        assert_mat4_near(
+            Mat4::from_rotation(a * b),
+            Mat4::from_rotation(a) * Mat4::from_rotation(b),
+        );
+        assert_quat_near(a * Quat::identity(), a);
+
+        let mut c = a;
+        c *= b;
+        assert_eq!(c, a * b);
+    }
+
+    #[test]
+    fn euler_angles_turn_about_x_then_y_then_z() {
+        let quarter = std::f32::consts::FRAC_PI_2;
+        let euler = Quat::from_euler(quarter, quarter, 0.0);
+        // y goes to z about x, then z goes to x about y
+        assert_vec3_near(euler.rotate(Vec3::y()), Vec3::x());
+
+        assert_quat_near(
+            Quat::from_euler(0.0, 0.0, 0.4),
+            Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), 0.4),
+        );
+    }
+
+    #[test]
+    fn slerp_keeps_unit_length_and_ends() {
+        let a = Quat::from_euler(0.3, -1.1, 2.0);
+        let b = Quat::from_axis_angle(Vec3::new(1.0, 2.0, 3.0), 2.5);
+        assert_quat_near(a.slerp(&b, 0.0), a);
+        assert_quat_near(a.slerp(&b, 1.0), b);
+        for i in 0..=10 {
+            let q = a.slerp(&b, i as f32 / 10.0);
+            assert!((q.dot(&q) - 1.0).abs() < 1e-5);
+        }
+    }
+
+    /// Deterministic xorshift so property tests see the same cases every
+    /// run
+    struct Cases(u32);
+
+    impl Cases {
+        fn new() -> Self {
+            Self(0x2545_f491)
+        }
+        /// Uniform in -10..10
+        fn f32(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 as f32 / u32::MAX as f32) * 20.0 - 10.0
+        }
+        fn vec2(&mut self) -> Vec2 {
+            Vec2::new(self.f32(), self.f32())
+        }
+        fn vec3(&mut self) -> Vec3 {
+            Vec3::new(self.f32(), self.f32(), self.f32())
+        }
+        fn vec4(&mut self) -> Vec4 {
+            Vec4::new(self.f32(), self.f32(), self.f32(), self.f32())
+        }
+        fn quat(&mut self) -> Quat {
+            Quat::from_axis_angle(self.vec3(), self.f32())
+        }
+        /// Rotated, scaled and translated, never singular
+        fn transform(&mut self) -> Mat4 {
+            let mut scale = || self.f32().abs() + 0.5;
+            let scale = Vec3::new(scale(), scale(), scale());
+            Mat4::from_trs(self.vec3(), self.quat(), scale)
+        }
+    }
+
+    const CASES: usize = 200;
+
+    /// Within `tolerance` relative to the size of the numbers involved
+    fn near(a: f32, b: f32, tolerance: f32) -> bool {
+        (a - b).abs() <= tolerance * a.abs().max(b.abs()).max(1.0)
+    }
+
+    #[test]
+    fn inverse_round_trips() {
+        let mut cases = Cases::new();
+        for _ in 0..CASES {
+            let a = cases.transform();
+            let b = cases.transform();
+            let inverse = a.inverse().unwrap();
+            assert_mat4_near(a * inverse, Mat4::identity());
+            assert_mat4_near(inverse.inverse().unwrap(), a);
+
+            let (ab, det_a, det_b) =
+                ((a * b).determinant(), a.determinant(), b.determinant());
+            assert!(near(ab, det_a * det_b, 1e-3), "{ab} != {det_a} * {det_b}");
+            assert_eq!(a.transpose().transpose(), a);
+        }
+    }
+
+    #[test]
+    fn matrices_transform_vectors_in_order() {
+        let mut cases = Cases::new();
+        for _ in 0..CASES {
+            let (a, b) = (cases.transform(), cases.transform());
+            let v = cases.vec4();
+            let (left, right) = ((a * b) * v, b * (a * v));
+            for (l, r) in [
+                (left.x, right.x),
+                (left.y, right.y),
+                (left.z, right.z),
+                (left.w, right.w),
+            ] {
+                assert!(near(l, r, 1e-4), "{left:?} != {right:?}");
+            }
+
+            // Points are vectors with w of 1
+            let point = cases.vec3();
+            let moved = a * Vec4::new(point.x, point.y, point.z, 1.0);
+            assert_vec3_near(
+                a.transform_point(point),
+                Vec3::new(moved.x, moved.y, moved.z),
+            );
+            assert_eq!(Mat4::identity() * v, v);
+        }
+    }
+
+    #[test]
+    fn look_at_puts_the_target_in_front() {
+        let mut cases = Cases::new();
+        for _ in 0..CASES {
+            let (eye, target) = (cases.vec3(), cases.vec3());
+            let view = Mat4::look_at_rh(eye, target, Vec3::y());
+            assert_vec3_near(view.transform_point(eye), Vec3::zeroes());
+            // Right handed, the camera looks down -z
+            let ahead = view.transform_point(target);
+            let distance = eye.distance(&target);
+            assert_vec3_near(ahead, Vec3::new(0.0, 0.0, -distance));
+        }
+    }
+
+    #[test]
+    fn perspective_maps_near_and_far_to_the_ends_of_depth() {
+        let projection =
+            Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 2.0, 0.5, 100.0);
+        let project = |z: f32| {
+            let clip = projection * Vec4::new(0.0, 0.0, z, 1.0);
+            clip.z / clip.w
+        };
+        assert!(near(project(-0.5), 0.0, 1e-5));
+        assert!(near(project(-100.0), 1.0, 1e-5));
+
+        // A quarter turn field of view sees 45 degrees up, and twice as far
+        // to the side
+        let top = projection * Vec4::new(0.0, 1.0, -1.0, 1.0);
+        assert!(near(top.y / top.w, 1.0, 1e-5));
+        let side = projection * Vec4::new(2.0, 0.0, -1.0, 1.0);
+        assert!(near(side.x / side.w, 1.0, 1e-5));
+    }
+
+    #[test]
+    fn vec3_identities() {
+        let mut cases = Cases::new();
+        for _ in 0..CASES {
+            let (a, b, c) = (cases.vec3(), cases.vec3(), cases.vec3());
+            let s = cases.f32();
+
+            assert!(near(a.dot(&b), b.dot(&a), 1e-6));
+            let cross = a.cross(&b);
+            assert!(near(cross.dot(&a), 0.0, 1e-3));
+            assert!(near(cross.dot(&b), 0.0, 1e-3));
+            assert_vec3_near(a.cross(&b), -b.cross(&a));
+
+            assert!(a.distance(&c) <= a.distance(&b) + b.distance(&c) + 1e-4);
+            assert!(near(a.distance(&b), b.distance(&a), 1e-6));
+            assert_vec3_near(a.lerp(&b, 0.0), a);
+            assert_vec3_near(a.lerp(&b, 1.0), b);
+            assert_vec3_near(a.lerp(&b, 0.5), (a + b) / 2.0);
+
+            assert_vec3_near(a * b, b * a);
+            assert_vec3_near(a * Vec3::xyz(s), a * s);
+            if a.len() > 1e-3 {
+                assert!(near(a.normalise().len(), 1.0, 1e-5));
+            }
+
+            let mut d = a;
+            d -= b;
+            assert_eq!(d, a - b);
+            d *= s;
+            assert_eq!(d, (a - b) * s);
+            d += b;
+            assert_eq!(d, (a - b) * s + b);
+        }
+        assert_eq!(Vec3::zeroes().normalise(), Vec3::zeroes());
+    }
+
+    #[test]
+    fn vec2_identities() {
+        let mut cases = Cases::new();
+        for _ in 0..CASES {
+            let (a, b) = (cases.vec2(), cases.vec2());
+            let s = cases.f32();
+
+            assert!(near(a.dot(&b), b.dot(&a), 1e-6));
+            assert!(near(a.dot(&a), a.len() * a.len(), 1e-5));
+            assert_eq!(a + (-a), Vec2::zeroes());
+            assert_eq!(a * b, b * a);
+            assert_eq!(a * Vec2::new(s, s), a * s);
+            if a.len() > 1e-3 {
+                assert!(near(a.normalise().len(), 1.0, 1e-5));
+                assert!(near((a / a.len()).len(), 1.0, 1e-5));
+            }
+
+            let mut c = a;
+            c += b;
+            c -= a;
+            c *= 2.0;
+            assert!(near(c.x, b.x * 2.0, 1e-5) && near(c.y, b.y * 2.0, 1e-5));
+        }
+        assert_eq!(Vec2::from([1.0, 2.0]), Vec2::new(1.0, 2.0));
+    }
 }